@@ -1,49 +1,62 @@
-use crate::core::bitcoin::{bitcoincore_rpc, EnrichedHeader};
-use bitcoincore_rpc::{Auth, Client, Error as RpcError, RpcApi};
+//! Prints the genesis `app_state` which starts the peg's SPV client from the
+//! Bitcoin header at the given height, fetched from a local full node.
+//!
+//! Usage: make-checkpoint-header <network> <height>
+
+use nomic::core::bitcoin::bitcoincore_rpc::RpcApi;
+use nomic::core::bitcoin::{Network, SpvCheckpoint};
+use nomic::relayer::relayer::make_rpc_client;
 use std::env;
-use std::fs;
-use std::path::Path;
+use std::process;
 
-const BITCOIN_START_HEIGHT: usize = 1721664;
-fn make_rpc_client() -> Result<Client, RpcError> {
-    let rpc_user = env::var("BTC_RPC_USER").unwrap();
-    let rpc_pass = env::var("BTC_RPC_PASS").unwrap();
-    let rpc_auth = Auth::UserPass(rpc_user, rpc_pass);
-    let rpc_url = "http://localhost:18332";
-    Client::new(rpc_url.to_string(), rpc_auth)
+fn usage() -> ! {
+    eprintln!("Usage: make-checkpoint-header <network> <height>");
+    process::exit(1);
 }
-/// Get the latest checkpoint header from rpc
-fn get_checkpoint_header() -> EnrichedHeader {
-    let rpc = make_rpc_client().unwrap();
-    let best_block_hash = rpc.get_best_block_hash().unwrap();
-    let mut header = rpc.get_block_header_verbose(&best_block_hash).unwrap();
-    if header.height < BITCOIN_START_HEIGHT {
-        panic!("Start and sync a Bitcoin testnet full node before starting the peg ABCI state machine.");
+
+/// Get the header at `height` in the full node's longest chain.
+fn get_checkpoint_header(network: Network, height: u32) -> SpvCheckpoint {
+    let rpc = make_rpc_client(network).expect("Failed to create Bitcoin RPC client");
+    let tip_height = rpc.get_block_count().expect("Failed to get Bitcoin tip");
+    if tip_height < height as u64 {
+        panic!(
+            "Bitcoin {} full node is at height {}, sync it past {} first.",
+            network, tip_height, height
+        );
     }
-    loop {
-        if header.height == BITCOIN_START_HEIGHT {
-            return EnrichedHeader {
-                header: rpc.get_block_header_raw(&header.hash).unwrap(),
-                height: header.height as u32,
-            };
-        }
-        header = rpc
-            .get_block_header_verbose(&header.previousblockhash.unwrap())
-            .unwrap();
+
+    let hash = rpc
+        .get_block_hash(height as u64)
+        .expect("Failed to get block hash");
+    let header = rpc
+        .get_block_header_raw(&hash)
+        .expect("Failed to get block header");
+    let info = rpc
+        .get_block_header_verbose(&hash)
+        .expect("Failed to get block header info");
+
+    SpvCheckpoint {
+        height,
+        header,
+        chainwork: hex::encode(info.chainwork),
     }
 }
 
 fn main() {
-    let header_info = get_checkpoint_header();
-    let header_info_encoded: String =
-        serde_json::to_string(&header_info).expect("Failed to serialize checkpoint header info");
-    println!("header: {}", header_info_encoded);
-    let header_path = Path::new(env!("CARGO_MANIFEST_DIR"))
-        .join("..")
-        .join("..")
-        .join("config")
-        .join("header.json");
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 3 {
+        usage();
+    }
+    let network: Network = args[1].parse().unwrap_or_else(|_| usage());
+    let height: u32 = args[2].parse().unwrap_or_else(|_| usage());
 
-    fs::write(header_path, header_info_encoded)
-        .expect("Failed to write serialized checkpoint header");
+    let checkpoint = get_checkpoint_header(network, height);
+    let app_state = serde_json::json!({
+        "network": network,
+        "checkpoint": checkpoint,
+    });
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&app_state).expect("Failed to serialize app state")
+    );
 }
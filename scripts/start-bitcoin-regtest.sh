@@ -1 +1 @@
-bitcoind -regtest -rpcuser=$BTC_RPC_USER -rpcpassword=$BTC_RPC_PASS -rpcport=18332
+bitcoind -regtest -rpcuser=$BTC_RPC_USER -rpcpassword=$BTC_RPC_PASS -rpcport=18443
@@ -5,6 +5,7 @@ use log::{debug, info};
 use nomic::chain::chain::abci_server;
 use nomic::chain::client::Client;
 use nomic::cli::wallet::Wallet;
+use nomic::core::bitcoin::{bitcoin, Network};
 use nomic::Result;
 use std::{env, fs};
 
@@ -49,10 +50,30 @@ enum SubCommand {
     /// Withdraw coins to a Bitcoin address
     #[clap(name = "withdraw")]
     Withdraw(Withdraw),
+
+    /// Check which signatory sets a Bitcoin transaction pays to or spends from
+    #[clap(name = "audit")]
+    Audit(Audit),
+
+    /// Check that the NBTC supply is backed by the Bitcoin reserve
+    #[clap(name = "reserves")]
+    Reserves(Reserves),
+
+    /// Signal that the local validator wants to pause the peg
+    #[clap(name = "pause")]
+    Pause(Pause),
+
+    /// Signal that the local validator wants to resume the peg
+    #[clap(name = "resume")]
+    Resume(Resume),
 }
 
 #[derive(Clap)]
-struct Start;
+struct Start {
+    /// Bitcoin network for a new development chain (bitcoin, testnet, regtest or signet)
+    #[clap(long = "network", default_value = "testnet")]
+    network: Network,
+}
 
 #[derive(Clap)]
 struct Relayer;
@@ -78,6 +99,25 @@ struct Withdraw {
     amount: f64,
 }
 
+#[derive(Clap)]
+struct Reserves;
+
+#[derive(Clap)]
+struct Pause;
+
+#[derive(Clap)]
+struct Resume;
+
+#[derive(Clap)]
+struct Audit {
+    /// Hex-encoded raw Bitcoin transaction
+    tx: String,
+
+    /// Sidechain address of a depositor the transaction may pay to
+    #[clap(long = "recipient")]
+    recipients: Vec<String>,
+}
+
 pub fn main() {
     let opts: Opts = Opts::parse();
 
@@ -105,11 +145,11 @@ pub fn main() {
             default_log_level("info");
             nomic::relayer::relayer::start();
         }
-        SubCommand::Start(_) => {
+        SubCommand::Start(start) => {
             default_log_level("info");
             // Install and start Tendermint
             nomic::cli::tendermint::install(&nomic_home);
-            nomic::cli::tendermint::init(&nomic_home, opts.dev);
+            nomic::cli::tendermint::init(&nomic_home, opts.dev, start.network);
             nomic::cli::tendermint::start(&nomic_home);
 
             // Start the ABCI server
@@ -156,9 +196,9 @@ pub fn main() {
 
             let wallet_path = nomic_home.join("wallet.key");
             let wallet = Wallet::load_or_generate(wallet_path).unwrap();
-            let address = wallet.deposit_address(&signatory_snapshot.signatories);
+            let network = client.get_network().unwrap();
+            let address = wallet.deposit_address(&signatory_snapshot, network);
 
-            use nomic::chain::chain::peg::{CHECKPOINT_INTERVAL, SIGNATORY_CHANGE_INTERVAL};
             use std::time::{SystemTime, UNIX_EPOCH};
             let now = SystemTime::now()
                 .duration_since(UNIX_EPOCH)
@@ -178,9 +218,10 @@ pub fn main() {
                 .checkpoint_index
                 .get_or_default()
                 .unwrap();
-            let checkpoints_until_change =
-                SIGNATORY_CHANGE_INTERVAL - (checkpoint_index % SIGNATORY_CHANGE_INTERVAL);
-            let time_until_change = (checkpoints_until_change * CHECKPOINT_INTERVAL)
+            let params = client.state().unwrap().peg.params().unwrap();
+            let checkpoints_until_change = params.signatory_change_interval
+                - (checkpoint_index % params.signatory_change_interval);
+            let time_until_change = (checkpoints_until_change * params.checkpoint_interval)
                 .saturating_sub(now - last_checkpoint_time);
             let hours_until_expiration =
                 (time_until_change as f64 / (60.0 * 60.0)).round() as usize;
@@ -216,7 +257,10 @@ pub fn main() {
                 .bold()
             );
             println!();
-            println!("Send testnet Bitcoin to this address to deposit into your");
+            println!(
+                "Send {} Bitcoin to this address to deposit into your",
+                network
+            );
             println!("sidechain account. After the transaction has been confirmed,");
             println!(
                 "you can check your balance with `{}`.",
@@ -288,6 +332,180 @@ pub fn main() {
                 withdrawal.bitcoin_address
             );
         }
+
+        SubCommand::Audit(audit) => {
+            default_log_level("warn");
+
+            let tx_bytes = hex::decode(audit.tx.as_str()).unwrap();
+            let tx: bitcoin::Transaction =
+                bitcoin::consensus::encode::deserialize(tx_bytes.as_slice()).unwrap();
+            let recipients: Vec<Vec<u8>> = audit
+                .recipients
+                .iter()
+                .map(|address| nomic::cli::wallet::decode_address(address.as_str()).unwrap())
+                .collect();
+
+            let client = Client::new("localhost:26657").unwrap();
+            let signatory_sets = client.get_signatory_set_snapshots().unwrap();
+
+            let report = nomic::cli::audit::audit_tx(&tx, &signatory_sets, &recipients);
+
+            println!("TRANSACTION:");
+            println!("{}", tx.txid().to_string().cyan().bold());
+            println!();
+
+            println!("RESERVE OUTPUTS:");
+            if report.outputs.is_empty() {
+                println!("{}", "None found".yellow());
+            }
+            for output in report.outputs.iter() {
+                let recipient = if output.data.is_empty() {
+                    "checkpoint change".to_string()
+                } else {
+                    nomic::cli::wallet::encode_address(output.data.as_slice())
+                };
+                let set = format!("signatory set {}", output.signatory_set_index);
+                println!(
+                    "#{}: {} BTC to {} ({}), {}",
+                    output.index,
+                    format_amount(output.value).cyan().bold(),
+                    if output.current {
+                        set.green().bold()
+                    } else {
+                        set.yellow().bold()
+                    },
+                    if output.current {
+                        "current"
+                    } else {
+                        "not current"
+                    },
+                    recipient
+                );
+            }
+            println!();
+
+            println!("RESERVE INPUTS:");
+            if report.inputs.is_empty() {
+                println!("{}", "None found".yellow());
+            }
+            for input in report.inputs.iter() {
+                let set = match input.signatory_set_index {
+                    Some(index) => format!("signatory set {}", index).green().bold(),
+                    None => "an unknown signatory set".red().bold(),
+                };
+                println!(
+                    "#{}: spends from {}, threshold {} of {} truncated voting power",
+                    input.index,
+                    set,
+                    input.script.threshold,
+                    input.script.signatories.total_voting_power()
+                );
+                for signatory in input.script.signatories.iter() {
+                    println!("    {} {}", signatory.pubkey, signatory.voting_power);
+                }
+                if let Some(recovery) = &input.script.recovery {
+                    println!(
+                        "    recovery: {} of {} keys after {} blocks",
+                        recovery.threshold,
+                        recovery.pubkeys.len(),
+                        recovery.timelock
+                    );
+                }
+            }
+        }
+
+        SubCommand::Reserves(_) => {
+            default_log_level("warn");
+
+            let client = Client::new("localhost:26657").unwrap();
+            let report = client.reserve_report().unwrap();
+
+            println!("NBTC SUPPLY:");
+            println!("{} NBTC", format_amount(report.supply).cyan().bold());
+            println!();
+            println!("BITCOIN RESERVE:");
+            println!(
+                "Pending reserve outputs:        {}",
+                format_amount(report.utxo_value)
+            );
+            println!(
+                "Spent by active checkpoint:     {}",
+                format_amount(report.active_input_value)
+            );
+            println!(
+                "Paid out by active checkpoint: -{}",
+                format_amount(report.active_withdrawal_value)
+            );
+            println!(
+                "Active checkpoint fee:         -{}",
+                format_amount(report.active_fee)
+            );
+            println!(
+                "Pending withdrawals:           -{}",
+                format_amount(report.pending_withdrawal_value)
+            );
+            println!(
+                "Fees not paid by withdrawals:   {}",
+                format_signed_amount(report.unbacked_fees())
+            );
+            println!(
+                "{} BTC",
+                format_signed_amount(report.backing()).cyan().bold()
+            );
+            println!();
+
+            if let Some((value, false)) = report.finalized_change {
+                let message = format!(
+                    "The change output of the last finalized checkpoint ({} BTC) is missing from the reserve.",
+                    format_amount(value)
+                );
+                println!("{}", message.red().bold());
+            }
+            let discrepancy = report.discrepancy();
+            if discrepancy == 0 {
+                println!("{}", "The NBTC supply is fully backed.".green().bold());
+            } else if discrepancy > 0 {
+                let message = format!(
+                    "The reserve holds {} BTC more than the NBTC supply.",
+                    format_signed_amount(discrepancy)
+                );
+                println!("{}", message.yellow().bold());
+            } else {
+                let message = format!(
+                    "The NBTC supply exceeds the reserve by {} BTC.",
+                    format_signed_amount(-discrepancy)
+                );
+                println!("{}", message.red().bold());
+            }
+
+            let reorged_deposits = client.get_reorged_deposits().unwrap();
+            if !reorged_deposits.is_empty() {
+                println!();
+                println!("REORGED DEPOSITS:");
+            }
+            for reorged in reorged_deposits {
+                let deposit = reorged.deposit;
+                println!(
+                    "{} {} BTC at height {}, {:?}",
+                    bitcoin::OutPoint::from(deposit.utxo.outpoint),
+                    format_amount(deposit.utxo.value).red().bold(),
+                    deposit.height,
+                    reorged.status
+                );
+            }
+        }
+
+        SubCommand::Pause(_) => {
+            default_log_level("warn");
+            nomic::signatory::signal_pause(&nomic_home, true).unwrap();
+            println!("Signaled to pause the peg.");
+        }
+
+        SubCommand::Resume(_) => {
+            default_log_level("warn");
+            nomic::signatory::signal_pause(&nomic_home, false).unwrap();
+            println!("Signaled to resume the peg.");
+        }
     }
 }
 
@@ -297,6 +515,11 @@ fn format_amount(amount: u64) -> String {
     format!("{}.{:0>8}", amount / COIN, (amount % COIN).to_string())
 }
 
+fn format_signed_amount(amount: i128) -> String {
+    let sign = if amount < 0 { "-" } else { "" };
+    format!("{}{}", sign, format_amount(amount.abs() as u64))
+}
+
 fn to_satoshis(amount: f64) -> u64 {
     (amount * COIN as f64).round() as u64
 }
@@ -1,6 +1,7 @@
 //!  Start the peg abci server.
 
-use super::state_machine::{initialize, run};
+use super::genesis::Genesis;
+use super::state_machine::run;
 use super::Action;
 use crate::core::primitives::transaction::Transaction;
 use failure::bail;
@@ -26,8 +27,9 @@ impl Application for App {
             validators.insert(pub_key, power);
         }
 
+        let genesis = Genesis::from_app_state(req.get_app_state_bytes())?;
+        run(&mut store, Action::InitChain(genesis), &mut validators)?;
         write_validators(&mut store, validators)?;
-        initialize(&mut store)?;
 
         Ok(ResponseInitChain::new())
     }
@@ -62,10 +64,12 @@ impl Application for App {
         let mut validators = read_validators(&mut store);
         match tx {
             Ok(tx) => match run(&mut store, Action::Transaction(tx), &mut validators) {
-                Ok(_execution_result) => {
+                Ok(events) => {
                     write_validators(&mut store, validators)?;
                     let mut res = ResponseDeliverTx::new();
                     res.set_data(vec![]);
+                    let events: Vec<Event> = events.into_iter().map(abci_event).collect();
+                    res.set_events(events.into());
                     Ok(res)
                 }
 
@@ -107,6 +111,19 @@ impl Application for App {
     }
 }
 
+/// Converts an event emitted by the state machine into an ABCI event.
+fn abci_event(event: super::Event) -> Event {
+    let mut abci_event = Event::new();
+    abci_event.set_field_type(event.kind);
+    for (key, value) in event.attributes {
+        abci_event.mut_attributes().push(Default::default());
+        let attribute = abci_event.mut_attributes().last_mut().unwrap();
+        attribute.set_key(key.into_bytes());
+        attribute.set_value(value.into_bytes());
+    }
+    abci_event
+}
+
 fn write_validators<S: Store>(mut store: S, validators: BTreeMap<Vec<u8>, u64>) -> OrgaResult<()> {
     let validator_map_bytes =
         bincode::serialize(&validators).expect("Failed to serialize validator map");
@@ -1,18 +1,64 @@
+use super::params::Params;
 use super::SECP;
 use crate::core::primitives::{transaction::TransferTransaction, Account, Address, Result};
 use failure::bail;
-use orga::{collections::Map, Store};
+use orga::{collections::Map, state, Store, Value};
 
-pub type State<S> = Map<S, Address, Account>;
+/// Account balances and the NBTC supply. Accounts used to be stored directly
+/// in this store, so nodes running older versions need a state reset.
+#[state]
+pub struct State {
+    pub accounts: Map<Address, Account>,
+    /// Total number of coins in all accounts. Deposits mint coins, while
+    /// withdrawals and transaction fees burn them.
+    pub supply: Value<u64>,
+}
+
+impl<S: Store> State<S> {
+    pub fn get(&self, address: Address) -> Result<Option<Account>> {
+        self.accounts.get(address)
+    }
+
+    pub fn insert(&mut self, address: Address, account: Account) -> Result<()> {
+        self.accounts.insert(address, account)
+    }
+
+    pub fn supply(&self) -> Result<u64> {
+        self.supply.get_or_default()
+    }
+
+    /// Credits newly issued coins to an account.
+    pub fn mint(&mut self, address: Address, amount: u64) -> Result<()> {
+        let mut account = self.get(address)?.unwrap_or_default();
+        account.balance += amount;
+        self.insert(address, account)?;
+        let supply = self.supply()?;
+        self.supply.set(supply + amount)
+    }
+
+    /// Removes coins from the total supply. The caller deducts them from the
+    /// balance of the account they are burned from.
+    pub fn burn(&mut self, amount: u64) -> Result<()> {
+        let supply = self.supply()?;
+        match supply.checked_sub(amount) {
+            Some(supply) => self.supply.set(supply),
+            None => bail!("Burned amount exceeds the supply"),
+        }
+    }
+}
 
 pub mod handlers {
     use super::*;
 
-    pub fn transfer_tx<S: Store>(accounts: &mut State<S>, tx: TransferTransaction) -> Result<()> {
+    pub fn transfer_tx<S: Store>(
+        accounts: &mut State<S>,
+        params: &Params,
+        tx: TransferTransaction,
+    ) -> Result<()> {
         if tx.from == tx.to {
             bail!("Account cannot send to itself");
         }
-        if tx.fee_amount < 1000 {
+        if tx.fee_amount < params.min_transfer_fee {
             bail!("Transaction fee is too small");
         }
         if tx.from.len() != 33 {
@@ -52,7 +98,8 @@ pub mod handlers {
         // Save updated accounts to store
         accounts.insert(unsafe_slice_to_address(&tx.from[..]), sender_account)?;
         accounts.insert(unsafe_slice_to_address(&tx.to[..]), recipient_account)?;
-        Ok(())
+        // Fees are not paid to anyone
+        accounts.burn(tx.fee_amount)
     }
 }
 
@@ -90,7 +137,7 @@ mod tests {
         let sig = sign(&mut tx, sender.privkey);
         tx.signature = sig;
 
-        handlers::transfer_tx(&mut accounts, tx).unwrap();
+        handlers::transfer_tx(&mut accounts, &Default::default(), tx).unwrap();
     }
 
     #[test]
@@ -114,7 +161,7 @@ mod tests {
         tx.signature = sig;
 
         let mut accounts = State::wrap_store(&mut net.store).unwrap();
-        handlers::transfer_tx(&mut accounts, tx).unwrap();
+        handlers::transfer_tx(&mut accounts, &Default::default(), tx).unwrap();
     }
 
     #[test]
@@ -137,7 +184,7 @@ mod tests {
         let sig = sign(&mut tx, sender.privkey);
         tx.signature = sig;
 
-        handlers::transfer_tx(&mut accounts, tx).unwrap();
+        handlers::transfer_tx(&mut accounts, &Default::default(), tx).unwrap();
     }
 
     #[test]
@@ -160,7 +207,7 @@ mod tests {
         let sig = sign(&mut tx, sender.privkey);
         tx.signature = sig;
 
-        handlers::transfer_tx(&mut accounts, tx).unwrap();
+        handlers::transfer_tx(&mut accounts, &Default::default(), tx).unwrap();
     }
 
     #[test]
@@ -184,7 +231,7 @@ mod tests {
         tx.signature = sig;
         tx.signature[10] ^= 1;
 
-        handlers::transfer_tx(&mut accounts, tx).unwrap();
+        handlers::transfer_tx(&mut accounts, &Default::default(), tx).unwrap();
     }
 
     #[test]
@@ -206,7 +253,7 @@ mod tests {
         let sig = sign(&mut tx, sender.privkey);
         tx.signature = sig;
 
-        handlers::transfer_tx(&mut accounts, tx).unwrap();
+        handlers::transfer_tx(&mut accounts, &Default::default(), tx).unwrap();
 
         assert_eq!(
             accounts
@@ -228,6 +275,45 @@ mod tests {
                 nonce: 1
             }
         );
+        assert_eq!(accounts.supply().unwrap(), 234);
+    }
+
+    #[test]
+    fn transfer_with_configured_fee() {
+        let mut net = MockNet::new();
+
+        let mut accounts = State::wrap_store(&mut net.store).unwrap();
+        let receiver_address = vec![124; 33];
+        let sender = create_sender(&mut accounts, 1234, 0);
+        let params = Params {
+            min_transfer_fee: 10,
+            ..Default::default()
+        };
+
+        let mut tx = TransferTransaction {
+            from: sender.address.clone(),
+            to: receiver_address,
+            signature: vec![],
+            amount: 100,
+            nonce: 0,
+            fee_amount: 10,
+        };
+        let sig = sign(&mut tx, sender.privkey);
+        tx.signature = sig;
+
+        handlers::transfer_tx(&mut accounts, &params, tx).unwrap();
+        assert_eq!(accounts.supply().unwrap(), 1224);
+    }
+
+    #[test]
+    #[should_panic(expected = "Burned amount exceeds the supply")]
+    fn burn_more_than_supply() {
+        let mut net = MockNet::new();
+
+        let mut accounts = State::wrap_store(&mut net.store).unwrap();
+        create_sender(&mut accounts, 1234, 0);
+
+        accounts.burn(1235).unwrap();
     }
     // TODO: test for transfer to self
 }
@@ -1,8 +1,18 @@
+use super::genesis::Genesis;
 use crate::core::primitives::transaction::Transaction;
 use orga::abci::messages::Header;
 
 #[derive(Clone, Debug)]
 pub enum Action {
+    InitChain(Genesis),
     BeginBlock(Header),
     Transaction(Transaction),
 }
+
+/// An event emitted while running an action, which is returned to Tendermint
+/// along with the result of the transaction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Event {
+    pub kind: String,
+    pub attributes: Vec<(String, String)>,
+}
@@ -0,0 +1,317 @@
+use super::{params, peg};
+use crate::core::bitcoin::bitcoin;
+use crate::core::bitcoin::{Network, SpvCheckpoint};
+use crate::core::primitives::{Address, Result};
+use crate::core::signatory_set::Recovery;
+use failure::bail;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Version of the genesis document format this build understands.
+pub const GENESIS_VERSION: u32 = 1;
+
+/// The typed `app_state` of the Tendermint genesis document, applied by
+/// `Action::InitChain`. Every field may be left out to use its default.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Genesis {
+    pub version: u32,
+    pub network: Network,
+    /// Header the SPV client starts from, overriding the network's default.
+    pub checkpoint: Option<SpvCheckpoint>,
+    /// Initial account balances. These coins are not backed by any reserve
+    /// output, so they are meant for test networks.
+    pub accounts: Vec<GenesisAccount>,
+    /// The first signatory set. If left out, it is taken from the validators of
+    /// the first block.
+    pub signatories: Option<Vec<GenesisSignatory>>,
+    pub params: params::Params,
+    pub peg: peg::Params,
+    /// Emergency spending path added to every reserve output. Reserve outputs
+    /// have none if left out.
+    pub recovery: Option<GenesisRecovery>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GenesisAccount {
+    /// Hex-encoded 33-byte public key.
+    pub address: String,
+    pub balance: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GenesisSignatory {
+    /// Hex-encoded 33-byte secp256k1 public key.
+    pub pubkey: String,
+    pub voting_power: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GenesisRecovery {
+    /// Relative timelock in blocks after which the recovery keys can spend a
+    /// reserve output.
+    pub timelock: u16,
+    pub threshold: u8,
+    /// Hex-encoded 33-byte secp256k1 public keys.
+    pub pubkeys: Vec<String>,
+}
+
+impl Default for Genesis {
+    fn default() -> Self {
+        Genesis {
+            version: GENESIS_VERSION,
+            network: Default::default(),
+            checkpoint: None,
+            accounts: vec![],
+            signatories: None,
+            params: Default::default(),
+            peg: Default::default(),
+            recovery: None,
+        }
+    }
+}
+
+impl Genesis {
+    /// Parses the `app_state` bytes of a `RequestInitChain`.
+    pub fn from_app_state(bytes: &[u8]) -> Result<Self> {
+        // genesis files written before app state existed leave it empty
+        if bytes.is_empty() {
+            return Ok(Default::default());
+        }
+        let genesis: Genesis = serde_json::from_slice(bytes)?;
+        if genesis.version != GENESIS_VERSION {
+            bail!(
+                "Unsupported genesis version {}, expected {}",
+                genesis.version,
+                GENESIS_VERSION
+            );
+        }
+        Ok(genesis)
+    }
+
+    /// Returns the initial balance of each account.
+    pub fn account_balances(&self) -> Result<Vec<(Address, u64)>> {
+        let mut balances = vec![];
+        for account in self.accounts.iter() {
+            let bytes = hex::decode(&account.address)?;
+            if bytes.len() != 33 {
+                bail!("Invalid genesis account address {}", account.address);
+            }
+            let mut address: Address = [0; 33];
+            address.copy_from_slice(bytes.as_slice());
+            balances.push((address, account.balance));
+        }
+        Ok(balances)
+    }
+
+    /// Returns the voting power of each genesis signatory by public key, if
+    /// the genesis lists signatories.
+    pub fn signatory_keys(&self) -> Result<Option<BTreeMap<Vec<u8>, u64>>> {
+        let signatories = match &self.signatories {
+            Some(signatories) => signatories,
+            None => return Ok(None),
+        };
+        if signatories.is_empty() {
+            bail!("Genesis signatory set is empty");
+        }
+
+        let mut keys = BTreeMap::new();
+        for signatory in signatories {
+            let pubkey = hex::decode(&signatory.pubkey)?;
+            if keys.insert(pubkey, signatory.voting_power).is_some() {
+                bail!("Duplicate genesis signatory {}", signatory.pubkey);
+            }
+        }
+        Ok(Some(keys))
+    }
+
+    /// Returns the validated recovery path, if the genesis has one.
+    pub fn recovery(&self) -> Result<Option<Recovery>> {
+        let recovery = match &self.recovery {
+            Some(recovery) => recovery,
+            None => return Ok(None),
+        };
+
+        let mut pubkeys = vec![];
+        for pubkey in recovery.pubkeys.iter() {
+            let bytes = hex::decode(pubkey)?;
+            if bytes.len() != 33 {
+                bail!("Invalid genesis recovery key {}", pubkey);
+            }
+            pubkeys.push(bitcoin::PublicKey::from_slice(bytes.as_slice())?);
+        }
+        let recovery = Recovery {
+            timelock: recovery.timelock,
+            threshold: recovery.threshold,
+            pubkeys,
+        };
+        recovery.validate()?;
+        Ok(Some(recovery))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::state_machine::{run, State};
+    use super::super::Action;
+    use super::*;
+    use crate::core::signatory_set::MAX_RECOVERY_KEYS;
+    use orga::{MapStore, WrapStore};
+
+    #[test]
+    fn empty_app_state() {
+        let genesis = Genesis::from_app_state(&[]).unwrap();
+        assert_eq!(genesis.version, GENESIS_VERSION);
+        assert_eq!(genesis.network, Network::Testnet);
+        assert_eq!(genesis.params, params::Params::default());
+        assert_eq!(genesis.peg, peg::Params::default());
+    }
+
+    #[test]
+    fn partial_app_state() {
+        let genesis = Genesis::from_app_state(
+            br#"{
+                "network": "regtest",
+                "peg": { "checkpoint_interval": 60 }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(genesis.network, Network::Regtest);
+        assert_eq!(genesis.peg.checkpoint_interval, 60);
+        assert_eq!(
+            genesis.peg.signatory_change_interval,
+            peg::SIGNATORY_CHANGE_INTERVAL
+        );
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let err = Genesis::from_app_state(br#"{ "version": 2 }"#).unwrap_err();
+        assert_eq!(err.to_string(), "Unsupported genesis version 2, expected 1");
+    }
+
+    #[test]
+    fn invalid_accounts() {
+        let genesis = Genesis {
+            accounts: vec![GenesisAccount {
+                address: "0102".to_string(),
+                balance: 1,
+            }],
+            ..Default::default()
+        };
+        assert!(genesis.account_balances().is_err());
+    }
+
+    #[test]
+    fn init_chain() {
+        // the secp256k1 generator point
+        let pubkey =
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798".to_string();
+        let genesis = Genesis {
+            network: Network::Regtest,
+            accounts: vec![GenesisAccount {
+                address: "03".repeat(33),
+                balance: 1_000,
+            }],
+            signatories: Some(vec![GenesisSignatory {
+                pubkey,
+                voting_power: 10,
+            }]),
+            params: params::Params {
+                min_transfer_fee: 10,
+                ..Default::default()
+            },
+            peg: peg::Params {
+                checkpoint_interval: 60,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let mut store = MapStore::new();
+        run(&mut store, Action::InitChain(genesis), &mut BTreeMap::new()).unwrap();
+
+        let state = State::wrap_store(&mut store).unwrap();
+        let account = state.accounts.get([3; 33]).unwrap().unwrap();
+        assert_eq!(account.balance, 1_000);
+        assert_eq!(state.accounts.supply().unwrap(), 1_000);
+        assert_eq!(state.peg.network().unwrap(), Network::Regtest);
+        assert_eq!(state.params.get().unwrap().min_transfer_fee, 10);
+        assert_eq!(state.peg.params().unwrap().checkpoint_interval, 60);
+        let signatories = state.peg.current_signatory_set().unwrap().signatories;
+        assert_eq!(signatories.len(), 1);
+        assert_eq!(signatories.total_voting_power(), 10);
+    }
+
+    /// Returns a recovery path with the secp256k1 generator point doubled as
+    /// its only key.
+    fn genesis_recovery(threshold: u8) -> GenesisRecovery {
+        GenesisRecovery {
+            timelock: 144,
+            threshold,
+            pubkeys: vec![
+                "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5".to_string(),
+            ],
+        }
+    }
+
+    #[test]
+    fn init_chain_with_recovery() {
+        let genesis = Genesis {
+            network: Network::Regtest,
+            signatories: Some(vec![GenesisSignatory {
+                pubkey: "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798"
+                    .to_string(),
+                voting_power: 10,
+            }]),
+            recovery: Some(genesis_recovery(1)),
+            ..Default::default()
+        };
+
+        let mut store = MapStore::new();
+        run(&mut store, Action::InitChain(genesis), &mut BTreeMap::new()).unwrap();
+
+        let state = State::wrap_store(&mut store).unwrap();
+        let recovery = state.peg.current_signatory_set().unwrap().recovery.unwrap();
+        assert_eq!(recovery.timelock, 144);
+        assert_eq!(recovery.threshold, 1);
+        assert_eq!(recovery.pubkeys.len(), 1);
+        assert_eq!(state.peg.recovery.get().unwrap(), Some(recovery));
+    }
+
+    #[test]
+    fn invalid_recovery() {
+        for recovery in vec![
+            genesis_recovery(0),
+            genesis_recovery(2),
+            GenesisRecovery {
+                timelock: 0,
+                ..genesis_recovery(1)
+            },
+            GenesisRecovery {
+                pubkeys: vec![genesis_recovery(1).pubkeys[0].clone(); MAX_RECOVERY_KEYS + 1],
+                ..genesis_recovery(1)
+            },
+        ] {
+            let genesis = Genesis {
+                recovery: Some(recovery),
+                ..Default::default()
+            };
+            let mut store = MapStore::new();
+            assert!(run(&mut store, Action::InitChain(genesis), &mut BTreeMap::new()).is_err());
+        }
+    }
+
+    #[test]
+    fn invalid_params() {
+        let genesis = Genesis {
+            peg: peg::Params {
+                signatory_change_interval: 0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut store = MapStore::new();
+        assert!(run(&mut store, Action::InitChain(genesis), &mut BTreeMap::new()).is_err());
+    }
+}
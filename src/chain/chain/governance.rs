@@ -0,0 +1,420 @@
+use super::SECP;
+use crate::core::primitives::{
+    transaction::{GovernanceTransaction, Proposal},
+    Address, Result,
+};
+use failure::bail;
+use orga::{
+    collections::{Deque, Map},
+    state, Decode, Encode, Store, Terminated, Value,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+/// Seconds a proposal stays open for voting before it expires.
+pub const VOTING_PERIOD: u64 = 60 * 60 * 24 * 7;
+
+/// Maximum number of open proposals a validator may have submitted, which
+/// bounds the proposals checked in each block.
+pub const MAX_OPEN_PROPOSALS: u64 = 4;
+
+#[state]
+pub struct State {
+    pub proposals: Map<u64, ProposalRecord>,
+    /// Id the next submitted proposal must use.
+    pub next_proposal_id: Value<u64>,
+    /// Ids of the proposals which are still open, oldest first. Closed ids
+    /// are dropped once they reach the front.
+    pub open_proposals: Deque<u64>,
+    /// Number of open proposals submitted by each validator.
+    pub open_proposal_counts: Map<Address, u64>,
+}
+
+/// The voting status of a proposal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProposalStatus {
+    /// Validators are still voting.
+    Open,
+    /// More than 2/3 of the voting power voted for the proposal and it was
+    /// applied.
+    Executed,
+    /// The proposal passed but could not be applied, e.g. because its
+    /// parameters were invalid.
+    Failed,
+    /// The voting period ended before the proposal passed.
+    Expired,
+}
+
+/// A proposal along with the validators who voted for it, kept in
+/// `State::proposals` by proposal id.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProposalRecord {
+    pub proposal: Proposal,
+    pub status: ProposalStatus,
+    /// Block time at which the proposal was submitted.
+    pub start_time: u64,
+    /// Public keys of the validators who voted for the proposal, starting with
+    /// the proposer.
+    pub voters: Vec<Vec<u8>>,
+}
+
+impl ProposalRecord {
+    /// Returns the voting power of the current validators who voted for the
+    /// proposal.
+    pub fn voting_power(&self, validators: &BTreeMap<Vec<u8>, u64>) -> u64 {
+        self.voters
+            .iter()
+            .filter_map(|voter| validators.get(voter))
+            .sum()
+    }
+
+    /// Returns true if more than 2/3 of the validators' voting power voted for
+    /// the proposal.
+    pub fn has_passed(&self, validators: &BTreeMap<Vec<u8>, u64>) -> bool {
+        let total_voting_power: u64 = validators.values().sum();
+        self.voting_power(validators) as u128 * 3 > total_voting_power as u128 * 2
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        bincode::deserialize(bytes).map_err(|err| failure::format_err!("{}", err))
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        bincode::serialize(self).map_err(|err| failure::format_err!("{}", err))
+    }
+}
+
+impl Encode for ProposalRecord {
+    fn encode_into<W: Write>(&self, dest: &mut W) -> Result<()> {
+        let bytes = ProposalRecord::encode(self)?;
+        dest.write_all(bytes.as_slice())?;
+        Ok(())
+    }
+
+    fn encoding_length(&self) -> Result<usize> {
+        let bytes = ProposalRecord::encode(self)?;
+        Ok(bytes.len())
+    }
+}
+
+impl Decode for ProposalRecord {
+    fn decode<R: Read>(mut input: R) -> Result<Self> {
+        let mut buf = vec![];
+        input.read_to_end(&mut buf)?;
+        ProposalRecord::decode(buf.as_slice())
+    }
+}
+
+impl !Terminated for ProposalRecord {}
+
+impl<S: Store> State<S> {
+    pub fn get(&self, id: u64) -> Result<Option<ProposalRecord>> {
+        self.proposals.get(id)
+    }
+
+    /// Returns the proposals which are still open, oldest first.
+    pub fn open_proposals(&self) -> Result<Vec<(u64, ProposalRecord)>> {
+        let mut proposals = vec![];
+        for id in self.open_proposals.iter() {
+            let id = id?;
+            let record = self.get(id)?.unwrap();
+            if record.status == ProposalStatus::Open {
+                proposals.push((id, record));
+            }
+        }
+        Ok(proposals)
+    }
+
+    /// Sets the final status of an open proposal. Its id is left in
+    /// `open_proposals` until it reaches the front.
+    pub fn close(&mut self, id: u64, status: ProposalStatus) -> Result<()> {
+        let mut record = match self.get(id)? {
+            Some(record) => record,
+            None => bail!("Proposal does not exist"),
+        };
+        if record.status != ProposalStatus::Open {
+            bail!("Proposal is not open");
+        }
+        record.status = status;
+
+        let proposer = unsafe_slice_to_address(record.voters[0].as_slice());
+        let open_count = self.open_proposal_counts.get(proposer)?.unwrap_or_default();
+        self.open_proposal_counts
+            .insert(proposer, open_count.saturating_sub(1))?;
+        self.proposals.insert(id, record)
+    }
+}
+
+pub mod handlers {
+    use super::*;
+
+    /// Submits a new proposal or adds a vote to an open one. Only validators
+    /// with voting power may do either.
+    pub fn governance_tx<S: Store>(
+        state: &mut State<S>,
+        validators: &BTreeMap<Vec<u8>, u64>,
+        now: u64,
+        tx: GovernanceTransaction,
+    ) -> Result<()> {
+        match validators.get(&tx.validator) {
+            Some(voting_power) if *voting_power > 0 => {}
+            _ => bail!("Sender is not a validator"),
+        }
+        if tx.validator.len() != 33 {
+            bail!("Invalid validator public key");
+        }
+        if !tx.verify_signature(&SECP)? {
+            bail!("Invalid signature");
+        }
+
+        match tx.proposal {
+            Some(proposal) => {
+                let id = state.next_proposal_id.get_or_default()?;
+                if tx.proposal_id != id {
+                    bail!("Invalid proposal id");
+                }
+                let proposer = unsafe_slice_to_address(tx.validator.as_slice());
+                let open_count = state
+                    .open_proposal_counts
+                    .get(proposer)?
+                    .unwrap_or_default();
+                if open_count >= MAX_OPEN_PROPOSALS {
+                    bail!("Validator has too many open proposals");
+                }
+                state
+                    .open_proposal_counts
+                    .insert(proposer, open_count + 1)?;
+                state.next_proposal_id.set(id + 1)?;
+                state.proposals.insert(
+                    id,
+                    ProposalRecord {
+                        proposal,
+                        status: ProposalStatus::Open,
+                        start_time: now,
+                        voters: vec![tx.validator],
+                    },
+                )?;
+                state.open_proposals.push_back(id)?;
+            }
+            None => {
+                let mut record = match state.get(tx.proposal_id)? {
+                    Some(record) => record,
+                    None => bail!("Proposal does not exist"),
+                };
+                if record.status != ProposalStatus::Open {
+                    bail!("Proposal is not open");
+                }
+                if record.voters.contains(&tx.validator) {
+                    bail!("Validator has already voted");
+                }
+                record.voters.push(tx.validator);
+                state.proposals.insert(tx.proposal_id, record)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Expires the open proposals whose voting period has ended and returns the
+    /// ones which have passed, oldest first. The caller applies each of them
+    /// and closes it with the result.
+    pub fn begin_block<S: Store>(
+        state: &mut State<S>,
+        validators: &BTreeMap<Vec<u8>, u64>,
+        now: u64,
+    ) -> Result<Vec<(u64, Proposal)>> {
+        // proposals are submitted in time order, so the expired ones are
+        // always at the front along with any closed ones
+        while !state.open_proposals.is_empty() {
+            let id = state.open_proposals.get(0)?;
+            let record = state.get(id)?.unwrap();
+            if record.status == ProposalStatus::Open {
+                if record.has_passed(validators)
+                    || now.saturating_sub(record.start_time) <= VOTING_PERIOD
+                {
+                    break;
+                }
+                state.close(id, ProposalStatus::Expired)?;
+            }
+            state.open_proposals.pop_front()?;
+        }
+
+        let mut passed = vec![];
+        for (id, record) in state.open_proposals()? {
+            if record.has_passed(validators) {
+                passed.push((id, record.proposal));
+            }
+        }
+        Ok(passed)
+    }
+}
+
+fn unsafe_slice_to_address(slice: &[u8]) -> Address {
+    // warning: only call this with a slice of length 33
+    let mut buf: Address = [0; 33];
+    buf.copy_from_slice(slice);
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_utils::{create_keypair, sign};
+    use super::*;
+    use orga::{MapStore, WrapStore};
+
+    /// Builds a transaction signed by the validator with key `key_byte`.
+    fn sign_tx(
+        key_byte: u8,
+        proposal_id: u64,
+        proposal: Option<Proposal>,
+    ) -> GovernanceTransaction {
+        let (privkey, pubkey) = create_keypair(key_byte);
+        let mut tx = GovernanceTransaction {
+            validator: pubkey.serialize().to_vec(),
+            proposal_id,
+            proposal,
+            signature: vec![],
+        };
+        tx.signature = sign(&mut tx, privkey);
+        tx
+    }
+
+    /// Returns validators with keys 1 to `count`, each with 10 voting power.
+    fn validator_set(count: u8) -> BTreeMap<Vec<u8>, u64> {
+        (1..=count)
+            .map(|i| (create_keypair(i).1.serialize().to_vec(), 10))
+            .collect()
+    }
+
+    #[test]
+    fn passes_with_two_thirds() {
+        let mut store = MapStore::new();
+        let mut state = State::wrap_store(&mut store).unwrap();
+        let validators = validator_set(3);
+
+        let tx = sign_tx(1, 0, Some(Proposal::RotateSignatories));
+        handlers::governance_tx(&mut state, &validators, 0, tx).unwrap();
+        assert_eq!(state.open_proposals().unwrap().len(), 1);
+
+        let tx = sign_tx(2, 0, None);
+        handlers::governance_tx(&mut state, &validators, 0, tx).unwrap();
+        assert!(handlers::begin_block(&mut state, &validators, 1)
+            .unwrap()
+            .is_empty());
+
+        let tx = sign_tx(3, 0, None);
+        handlers::governance_tx(&mut state, &validators, 0, tx).unwrap();
+        let passed = handlers::begin_block(&mut state, &validators, 1).unwrap();
+        assert_eq!(passed, vec![(0, Proposal::RotateSignatories)]);
+
+        state.close(0, ProposalStatus::Executed).unwrap();
+        assert!(state.open_proposals().unwrap().is_empty());
+        assert_eq!(
+            state.get(0).unwrap().unwrap().status,
+            ProposalStatus::Executed
+        );
+    }
+
+    #[test]
+    fn expires() {
+        let mut store = MapStore::new();
+        let mut state = State::wrap_store(&mut store).unwrap();
+        let validators = validator_set(3);
+
+        let tx = sign_tx(1, 0, Some(Proposal::SetPegPaused(true)));
+        handlers::governance_tx(&mut state, &validators, 100, tx).unwrap();
+        handlers::begin_block(&mut state, &validators, 100 + VOTING_PERIOD).unwrap();
+        assert_eq!(state.open_proposals().unwrap().len(), 1);
+        handlers::begin_block(&mut state, &validators, 101 + VOTING_PERIOD).unwrap();
+        assert!(state.open_proposals().unwrap().is_empty());
+        assert_eq!(
+            state.get(0).unwrap().unwrap().status,
+            ProposalStatus::Expired
+        );
+
+        let tx = sign_tx(2, 0, None);
+        assert!(handlers::governance_tx(&mut state, &validators, 200, tx).is_err());
+    }
+
+    #[test]
+    fn open_proposal_limit() {
+        let mut store = MapStore::new();
+        let mut state = State::wrap_store(&mut store).unwrap();
+        let validators = validator_set(3);
+
+        for id in 0..MAX_OPEN_PROPOSALS {
+            let tx = sign_tx(1, id, Some(Proposal::RotateSignatories));
+            handlers::governance_tx(&mut state, &validators, 0, tx).unwrap();
+        }
+        let tx = sign_tx(1, MAX_OPEN_PROPOSALS, Some(Proposal::RotateSignatories));
+        let err = handlers::governance_tx(&mut state, &validators, 0, tx).unwrap_err();
+        assert_eq!(err.to_string(), "Validator has too many open proposals");
+
+        // other validators are not limited by it
+        let tx = sign_tx(2, MAX_OPEN_PROPOSALS, Some(Proposal::RotateSignatories));
+        handlers::governance_tx(&mut state, &validators, 0, tx).unwrap();
+
+        // closing a proposal in the middle frees a slot, and its id is dropped
+        // once the ones before it are closed
+        state.close(1, ProposalStatus::Executed).unwrap();
+        assert!(state.close(1, ProposalStatus::Executed).is_err());
+        assert_eq!(state.open_proposals().unwrap().len(), 4);
+        assert_eq!(state.open_proposals.len(), 5);
+        let tx = sign_tx(1, 5, Some(Proposal::RotateSignatories));
+        handlers::governance_tx(&mut state, &validators, 0, tx).unwrap();
+
+        state.close(0, ProposalStatus::Executed).unwrap();
+        handlers::begin_block(&mut state, &validators, 1).unwrap();
+        assert_eq!(state.open_proposals.len(), 4);
+        assert_eq!(state.open_proposals.get(0).unwrap(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid proposal id")]
+    fn replayed_proposal() {
+        let mut store = MapStore::new();
+        let mut state = State::wrap_store(&mut store).unwrap();
+        let validators = validator_set(1);
+
+        let tx = sign_tx(1, 0, Some(Proposal::RotateSignatories));
+        handlers::governance_tx(&mut state, &validators, 0, tx.clone()).unwrap();
+        handlers::governance_tx(&mut state, &validators, 0, tx).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "Validator has already voted")]
+    fn double_vote() {
+        let mut store = MapStore::new();
+        let mut state = State::wrap_store(&mut store).unwrap();
+        let validators = validator_set(3);
+
+        let tx = sign_tx(1, 0, Some(Proposal::RotateSignatories));
+        handlers::governance_tx(&mut state, &validators, 0, tx).unwrap();
+        let tx = sign_tx(1, 0, None);
+        handlers::governance_tx(&mut state, &validators, 0, tx).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "Sender is not a validator")]
+    fn non_validator() {
+        let mut store = MapStore::new();
+        let mut state = State::wrap_store(&mut store).unwrap();
+        let validators = validator_set(1);
+
+        let tx = sign_tx(9, 0, Some(Proposal::RotateSignatories));
+        handlers::governance_tx(&mut state, &validators, 0, tx).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid signature")]
+    fn invalid_signature() {
+        let mut store = MapStore::new();
+        let mut state = State::wrap_store(&mut store).unwrap();
+        let validators = validator_set(2);
+
+        let mut tx = sign_tx(1, 0, Some(Proposal::RotateSignatories));
+        tx.validator = sign_tx(2, 0, None).validator;
+        handlers::governance_tx(&mut state, &validators, 0, tx).unwrap();
+    }
+}
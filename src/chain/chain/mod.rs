@@ -3,12 +3,15 @@ pub use orga;
 pub use state_machine::State;
 
 pub mod action;
-pub use action::Action;
+pub use action::{Action, Event};
 
 pub mod abci_server;
+pub mod genesis;
+pub mod governance;
 pub mod spv;
 
 mod accounts;
+pub mod params;
 pub mod peg;
 #[cfg(test)]
 mod test_utils;
@@ -0,0 +1,72 @@
+use crate::core::primitives::Result;
+use failure::bail;
+use orga::{Decode, Encode, Terminated, Value};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+
+/// Default minimum fee of a transfer transaction.
+pub const MIN_TRANSFER_FEE: u64 = 1000;
+/// Default minimum work value of a redeemable work proof. Each multiple of it
+/// grants one unit of voting power.
+pub const MIN_WORK: u64 = 1 << 20;
+
+/// Parameters of the account and work handlers, set at genesis. The peg's
+/// parameters are kept in the peg state, see `peg::Params`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Params {
+    pub min_transfer_fee: u64,
+    pub min_work: u64,
+}
+
+pub type State<S> = Value<S, Params>;
+
+impl Default for Params {
+    fn default() -> Self {
+        Params {
+            min_transfer_fee: MIN_TRANSFER_FEE,
+            min_work: MIN_WORK,
+        }
+    }
+}
+
+impl Params {
+    /// Rejects values the handlers can't operate with.
+    pub fn validate(&self) -> Result<()> {
+        if self.min_work == 0 {
+            bail!("Minimum work must be at least 1");
+        }
+        Ok(())
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        bincode::deserialize(bytes).map_err(|err| failure::format_err!("{}", err))
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        bincode::serialize(self).map_err(|err| failure::format_err!("{}", err))
+    }
+}
+
+impl Encode for Params {
+    fn encode_into<W: Write>(&self, dest: &mut W) -> Result<()> {
+        let bytes = Params::encode(self)?;
+        dest.write_all(bytes.as_slice())?;
+        Ok(())
+    }
+
+    fn encoding_length(&self) -> Result<usize> {
+        let bytes = Params::encode(self)?;
+        Ok(bytes.len())
+    }
+}
+
+impl Decode for Params {
+    fn decode<R: Read>(mut input: R) -> Result<Self> {
+        let mut buf = vec![];
+        input.read_to_end(&mut buf)?;
+        Params::decode(buf.as_slice())
+    }
+}
+
+impl !Terminated for Params {}
@@ -1,29 +1,70 @@
-use super::super::{accounts::State as AccountState, spv::headercache::HeaderCache, SECP};
-use super::{State as PegState, Utxo};
-use super::{CHECKPOINT_INTERVAL, CHECKPOINT_MINIMUM_VALUE, SIGNATORY_CHANGE_INTERVAL};
+use super::super::genesis::Genesis;
+use super::super::{accounts::State as AccountState, spv::headercache::HeaderCache, Event, SECP};
+use super::{
+    dust_limit, input_signatory_set_indexes, is_standard_withdrawal_script, CheckpointStatus,
+    Deposit, ReorgedDeposit, ReorgedUtxoStatus, SigningBackoff, State as PegState, Utxo,
+};
 use crate::core::bitcoin::bitcoin;
-use crate::core::bitcoin::EnrichedHeader;
+use crate::core::bitcoin::{EnrichedHeader, Network};
 use crate::core::primitives::{transaction::*, Address, Error, Result, Signature, Withdrawal};
-use crate::core::signatory_set::{Signatory, SignatorySet, SignatorySetSnapshot};
+use crate::core::signatory_set::{Signatory, SignatorySet, SignatorySetSnapshot, MAX_SIGNATORIES};
 use bitcoin::consensus::encode::Encodable;
 use bitcoin::hashes::Hash;
-use bitcoin::Network::Testnet as bitcoin_network;
+use bitcoin::BitcoinHash;
 use failure::bail;
-use log::info;
+use log::{info, warn};
 use orga::{abci::messages::Header, Store};
 use std::collections::BTreeMap;
 
-pub fn initialize<S: Store>(state: &mut PegState<S>) -> Result<()> {
-    // TODO: this should be an action
-    let checkpoint = get_checkpoint_header();
-    let mut header_cache = HeaderCache::new(bitcoin_network, &mut state.headers);
+/// Initializes the peg from the genesis document. The SPV client starts from
+/// the genesis checkpoint if given, or otherwise from the default checkpoint
+/// for the network. If the genesis lists signatories, they form the first
+/// signatory set instead of the validators of the first block. The genesis
+/// recovery path, if any, is added to every signatory set.
+pub fn initialize<S: Store>(state: &mut PegState<S>, genesis: &Genesis) -> Result<()> {
+    genesis.peg.validate()?;
+    let network = genesis.network;
+    state.network.set(network)?;
+    state.params.set(genesis.peg.clone())?;
+    state.recovery.set(genesis.recovery()?)?;
+
+    if let Some(signatory_keys) = genesis.signatory_keys()? {
+        // set before the first block, whose time is not known yet
+        state.signatory_sets.push_back(SignatorySetSnapshot {
+            time: 0,
+            signatories: signatories_from_validators(&signatory_keys)?,
+            recovery: state.recovery.get_or_default()?,
+        })?;
+    }
+
+    let (header, height, work) = match genesis.checkpoint.clone() {
+        Some(checkpoint) => (checkpoint.header, checkpoint.height, checkpoint.work()?),
+        None => {
+            let checkpoint = get_checkpoint_header(network);
+            (
+                checkpoint.header,
+                checkpoint.height,
+                checkpoint.header.work(),
+            )
+        }
+    };
+    let mut header_cache = HeaderCache::new(network, &mut state.headers);
 
     header_cache
-        .add_header_raw(checkpoint.header, checkpoint.height)
+        .add_header_raw_with_work(header, height, work)
         .map_err(|e| e.into())
 }
 
-fn get_checkpoint_header() -> EnrichedHeader {
+fn get_checkpoint_header(network: Network) -> EnrichedHeader {
+    // the bundled checkpoint is a testnet header, other networks are synced
+    // from their genesis block
+    if network != Network::Testnet {
+        return EnrichedHeader {
+            height: 0,
+            header: network.genesis_header(),
+        };
+    }
+
     let encoded_checkpoint = include_bytes!("../../../config/header.json");
     let checkpoint: EnrichedHeader = serde_json::from_slice(&encoded_checkpoint[..])
         .expect("Failed to deserialize checkpoint header");
@@ -36,6 +77,10 @@ pub fn deposit_tx<S: Store>(
     account_state: &mut AccountState<S>,
     deposit_transaction: DepositTransaction,
 ) -> Result<()> {
+    if peg_state.is_paused()? {
+        bail!("Peg is paused");
+    }
+
     // Hash transaction and check for duplicate
     let txid = deposit_transaction.tx.txid();
     if peg_state
@@ -46,14 +91,19 @@ pub fn deposit_tx<S: Store>(
     }
 
     // Fetch merkle root for this block by its height
-    let mut header_cache = HeaderCache::new(bitcoin_network, &mut peg_state.headers);
+    let network = peg_state.network()?;
+    let mut header_cache = HeaderCache::new(network, &mut peg_state.headers);
     let tx_height = deposit_transaction.height;
     let header = header_cache.get_header_for_height(tx_height)?;
 
-    let header_merkle_root = match header {
-        Some(header) => header.stored.header.merkle_root,
+    let (header_merkle_root, block_hash) = match header {
+        Some(header) => (
+            header.stored.header.merkle_root,
+            header.stored.header.bitcoin_hash().into_inner(),
+        ),
         None => bail!("Merkle root not found for deposit transaction"),
     };
+    let tip_height = header_cache.tip()?.map_or(0, |tip| tip.stored.height);
 
     // Verify proof against the merkle root
     let proof = deposit_transaction.proof;
@@ -68,9 +118,13 @@ pub fn deposit_tx<S: Store>(
         bail!("Proof merkle root does not match chain");
     }
 
+    // Deposits may pay to any of the recent signatory sets
+    let params = peg_state.params()?;
+    let signatory_sets = peg_state.recent_signatory_sets(params.deposit_signatory_set_window)?;
+
     // Ensure tx contains deposit outputs
     let mut recipients = deposit_transaction.recipients.iter().peekable();
-    let mut contains_deposit_outputs = false;
+    let mut deposit_utxos = vec![];
     for (i, txout) in deposit_transaction.tx.output.iter().enumerate() {
         let recipient = match recipients.peek() {
             Some(recipient) => recipient,
@@ -79,44 +133,44 @@ pub fn deposit_tx<S: Store>(
         if recipient.len() != 33 {
             bail!("Recipient must be 33 bytes");
         }
-        // TODO: support older signatory sets
-        let signatory_set_index = peg_state
-            .signatory_sets
-            .fixed_index(peg_state.signatory_sets.len() - 1);
-        let signatory_set = peg_state.current_signatory_set()?;
-        let expected_script = crate::core::signatory_set::output_script(
-            &signatory_set.signatories,
-            recipient.to_vec(),
-        );
-        if txout.script_pubkey != expected_script {
-            continue;
-        }
-
-        // mint coins
-        let depositor_address = unsafe_slice_to_address(recipient.as_slice());
-        let mut depositor_account = account_state.get(depositor_address)?.unwrap_or_default();
-        depositor_account.balance += txout.value;
-        account_state.insert(depositor_address, depositor_account)?;
+        let matching_set = signatory_sets.iter().find(|(_, snapshot)| {
+            txout.script_pubkey == snapshot.output_script(recipient.to_vec())
+        });
+        let signatory_set_index = match matching_set {
+            Some((index, _)) => *index,
+            None => continue,
+        };
 
-        // Add UTXO to peg_state
-        let utxo = Utxo {
+        deposit_utxos.push(Utxo {
             outpoint: bitcoin::OutPoint {
                 txid: deposit_transaction.tx.txid(),
                 vout: i as u32,
             }
             .into(),
-            signatory_set_index: signatory_set_index as u64,
+            signatory_set_index,
             data: recipient.to_vec(),
             value: txout.value,
-        };
-        peg_state.utxos.push_back(utxo)?;
-
-        contains_deposit_outputs = true;
+        });
     }
-    if !contains_deposit_outputs {
+    if deposit_utxos.is_empty() {
         bail!("Transaction does not contain any deposit outputs");
     }
 
+    // Mint coins now if the deposit is buried deep enough, otherwise hold it
+    // until enough headers have been relayed
+    for utxo in deposit_utxos {
+        let deposit = Deposit {
+            height: tx_height,
+            block_hash,
+            utxo,
+        };
+        if is_confirmed(tx_height, tip_height, params.deposit_confirmation_depth) {
+            credit_deposit(peg_state, account_state, deposit)?;
+        } else {
+            peg_state.pending_deposits.push_back(deposit)?;
+        }
+    }
+
     // Deposit is valid, mark transaction as processed
     peg_state
         .processed_deposit_txids
@@ -124,24 +178,201 @@ pub fn deposit_tx<S: Store>(
     Ok(())
 }
 
+fn is_confirmed(deposit_height: u32, tip_height: u32, depth: u32) -> bool {
+    tip_height >= deposit_height + depth
+}
+
+/// Mints the value of a deposit output to its recipient and adds the output to
+/// the reserve. The deposit is tracked in `recent_deposits` so it can be
+/// flagged if its block is later reorged out.
+fn credit_deposit<S: Store>(
+    peg_state: &mut PegState<S>,
+    account_state: &mut AccountState<S>,
+    deposit: Deposit,
+) -> Result<()> {
+    let utxo = deposit.utxo.clone();
+    let depositor_address = unsafe_slice_to_address(utxo.data.as_slice());
+    account_state.mint(depositor_address, utxo.value)?;
+
+    peg_state.utxos.push_back(utxo)?;
+    peg_state.recent_deposits.push_back(deposit)?;
+    Ok(())
+}
+
+/// Credits the pending deposits which have reached the required confirmation
+/// depth, keeping the rest queued, and stops tracking credited deposits which
+/// are buried deeper than the `deposit_reorg_tracking_depth` parameter.
+fn process_pending_deposits<S: Store>(
+    peg_state: &mut PegState<S>,
+    account_state: &mut AccountState<S>,
+) -> Result<()> {
+    let params = peg_state.params()?;
+    let network = peg_state.network()?;
+    let mut header_cache = HeaderCache::new(network, &mut peg_state.headers);
+    let tip_height = match header_cache.tip()? {
+        Some(tip) => tip.stored.height,
+        None => return Ok(()),
+    };
+
+    for _ in 0..peg_state.pending_deposits.len() {
+        let deposit = peg_state.pending_deposits.pop_front()?.unwrap();
+        if is_confirmed(
+            deposit.height,
+            tip_height,
+            params.deposit_confirmation_depth,
+        ) {
+            credit_deposit(peg_state, account_state, deposit)?;
+        } else {
+            peg_state.pending_deposits.push_back(deposit)?;
+        }
+    }
+
+    while !peg_state.recent_deposits.is_empty() {
+        let deposit = peg_state.recent_deposits.get(0)?;
+        if tip_height < deposit.height + params.deposit_reorg_tracking_depth {
+            break;
+        }
+        peg_state.recent_deposits.pop_front()?;
+    }
+
+    Ok(())
+}
+
+/// Handles the blocks removed from the SPV trunk by a reorg. Pending deposits
+/// proven against a removed block are dropped and their transactions unmarked
+/// as processed, so they can be proven again against the new chain. Credited
+/// deposits have already been minted, so they are recorded in
+/// `reorged_deposits` for operators to review, and their outputs are taken out
+/// of the reserve so no checkpoint tries to spend them. Returns a
+/// `dropped_deposit` or `reorged_deposit` event for each of them.
+fn unwind_deposits<S: Store>(
+    peg_state: &mut PegState<S>,
+    unwound: &[[u8; 32]],
+) -> Result<Vec<Event>> {
+    let mut events = vec![];
+    for _ in 0..peg_state.pending_deposits.len() {
+        let deposit = peg_state.pending_deposits.pop_front()?.unwrap();
+        if unwound.contains(&deposit.block_hash) {
+            warn!(
+                "dropping pending deposit {:?} after its block was reorged out",
+                deposit.utxo.outpoint
+            );
+            peg_state
+                .processed_deposit_txids
+                .delete(deposit.utxo.outpoint.txid)?;
+            events.push(deposit_event("dropped_deposit", &deposit.utxo));
+        } else {
+            peg_state.pending_deposits.push_back(deposit)?;
+        }
+    }
+
+    for _ in 0..peg_state.recent_deposits.len() {
+        let deposit = peg_state.recent_deposits.pop_front()?.unwrap();
+        if unwound.contains(&deposit.block_hash) {
+            let status = remove_reorged_utxo(peg_state, &deposit.utxo)?;
+            warn!(
+                "credited deposit {:?} of {} sats was reorged out ({:?})",
+                deposit.utxo.outpoint, deposit.utxo.value, status
+            );
+            let mut event = deposit_event("reorged_deposit", &deposit.utxo);
+            event
+                .attributes
+                .push(("status".to_string(), format!("{:?}", status)));
+            events.push(event);
+            peg_state.reorged_deposits.push_back(ReorgedDeposit {
+                deposit,
+                time: peg_state.block_time.get_or_default()?,
+                status,
+            })?;
+        } else {
+            peg_state.recent_deposits.push_back(deposit)?;
+        }
+    }
+
+    Ok(events)
+}
+
+/// Builds an event of the given kind identifying a deposit's output.
+fn deposit_event(kind: &str, utxo: &Utxo) -> Event {
+    let outpoint = bitcoin::OutPoint::from(utxo.outpoint.clone());
+    Event {
+        kind: kind.to_string(),
+        attributes: vec![
+            ("txid".to_string(), outpoint.txid.to_string()),
+            ("outpoint".to_string(), outpoint.to_string()),
+            ("amount".to_string(), utxo.value.to_string()),
+        ],
+    }
+}
+
+/// Takes the output of a reorged deposit out of the reserve, aborting the
+/// active checkpoint first if it spends the output.
+fn remove_reorged_utxo<S: Store>(
+    peg_state: &mut PegState<S>,
+    utxo: &Utxo,
+) -> Result<ReorgedUtxoStatus> {
+    if peg_state.remove_pending_utxo(&utxo.outpoint)? {
+        return Ok(ReorgedUtxoStatus::Removed);
+    }
+
+    let in_active_checkpoint = peg_state.active_checkpoint.is_active.get_or_default()?
+        && peg_state
+            .active_utxos()?
+            .iter()
+            .any(|input| input.outpoint == utxo.outpoint);
+    if in_active_checkpoint {
+        abort_checkpoint(peg_state)?;
+        peg_state.remove_pending_utxo(&utxo.outpoint)?;
+        return Ok(ReorgedUtxoStatus::CheckpointAborted);
+    }
+
+    Ok(ReorgedUtxoStatus::SpentByCheckpoint)
+}
+
 pub fn begin_block<S: Store>(
     state: &mut PegState<S>,
     validators: &BTreeMap<Vec<u8>, u64>,
     header: Header,
 ) -> Result<()> {
     let now = header.get_time().get_seconds() as u64;
+    state.block_time.set(now)?;
 
     if let None = state.signatory_sets.back()? {
         // init signatories at start of chain
         let signatories = SignatorySetSnapshot {
             time: now,
             signatories: signatories_from_validators(validators)?,
+            recovery: state.recovery.get_or_default()?,
         };
         state.signatory_sets.push_back(signatories)?;
     }
 
+    let params = state.params()?;
+    if state.active_checkpoint.is_active.get_or_default()? {
+        let start_time = state.active_checkpoint.start_time.get_or_default()?;
+        if now.saturating_sub(start_time) > params.checkpoint_signing_timeout {
+            warn!(
+                "checkpoint {} was not signed in time",
+                state.checkpoint_index.get_or_default()?
+            );
+            // Only the sets holding up a checkpoint the others signed are
+            // skipped, a checkpoint no set signed is retried as before
+            let set_count = input_signatory_set_indexes(&state.active_utxos()?).len();
+            let unsigned = state.unsigned_signatory_sets()?;
+            if unsigned.len() < set_count {
+                for index in unsigned {
+                    back_off_signatory_set(state, index, now)?;
+                }
+            }
+            abort_checkpoint(state)?;
+            if params.checkpoint_timeout_resets_signatories {
+                rotate_signatories(state, validators, now)?;
+            }
+        }
+    }
+
     let time_since_last_checkpoint = now - state.last_checkpoint_time.get_or_default()?;
-    if time_since_last_checkpoint > CHECKPOINT_INTERVAL {
+    if time_since_last_checkpoint > params.checkpoint_interval {
         state.last_checkpoint_time.set(now)?;
 
         if state.pending_utxos()?.is_empty() {
@@ -152,8 +383,32 @@ pub fn begin_block<S: Store>(
             return Ok(());
         }
 
-        let utxo_total_value: u64 = state.pending_utxos()?.iter().map(|utxo| utxo.value).sum();
-        if utxo_total_value < CHECKPOINT_MINIMUM_VALUE {
+        if state.is_paused()? {
+            return Ok(());
+        }
+
+        // Take as much of the pending work as fits in one transaction, the rest
+        // carries over to the next checkpoint
+        let fee_rate = state.fee_rate()?;
+        let (utxos, withdrawals) = state.checkpoint_selection(fee_rate)?;
+
+        // Outputs with a recovery path must be moved before its timelock
+        // expires, even if the checkpoint would otherwise be too small
+        let refresh_due = match state.reserve_refresh_interval()? {
+            Some(interval) => now - state.last_refresh_time.get_or_default()? > interval,
+            None => false,
+        };
+
+        let utxo_total_value: u64 = utxos.iter().map(|utxo| utxo.value).sum();
+        if utxo_total_value < params.checkpoint_minimum_value && !refresh_due {
+            return Ok(());
+        }
+
+        // Wait for more deposits if the reserve can't pay for the withdrawals
+        // and the checkpoint fee
+        let withdrawal_total_value: u64 = withdrawals.iter().map(|w| w.value).sum();
+        let fee = state.checkpoint_fee(&utxos, &withdrawals, fee_rate)?;
+        if utxo_total_value < withdrawal_total_value + fee {
             return Ok(());
         }
 
@@ -162,11 +417,8 @@ pub fn begin_block<S: Store>(
         state.checkpoint_index.set(checkpoint_index)?;
 
         state.active_checkpoint.is_active.set(true)?;
-
-        let signatories = state.current_signatory_set()?.signatories;
-        for _ in 0..signatories.len() {
-            state.active_checkpoint.signatures.push_back(None)?;
-        }
+        state.active_checkpoint.start_time.set(now)?;
+        state.active_checkpoint.fee_rate.set(fee_rate)?;
 
         let signatory_set_index = state
             .signatory_sets
@@ -176,16 +428,40 @@ pub fn begin_block<S: Store>(
             .signatory_set_index
             .set(signatory_set_index)?;
 
-        state.utxos.drain_into(&mut state.active_checkpoint.utxos)?;
-        state
-            .pending_withdrawals
-            .drain_into(&mut state.active_checkpoint.withdrawals)?;
+        // move the selected UTXOs out of the pending queue, keeping the rest
+        // in order
+        let mut selected = utxos.iter().peekable();
+        for _ in 0..state.utxos.len() {
+            let utxo = state.utxos.pop_front()?.unwrap();
+            if selected
+                .peek()
+                .map_or(false, |next| next.outpoint == utxo.outpoint)
+            {
+                selected.next();
+                state.active_checkpoint.utxos.push_back(utxo)?;
+            } else {
+                state.utxos.push_back(utxo)?;
+            }
+        }
+        for _ in 0..withdrawals.len() {
+            let withdrawal = state.pending_withdrawals.pop_front()?.unwrap();
+            state.active_checkpoint.withdrawals.push_back(withdrawal)?;
+        }
+
+        // One signature slot for each signatory of each set being spent from
+        for index in input_signatory_set_indexes(&state.active_utxos()?) {
+            let signatories = state.signatory_sets.get_fixed(index)?.signatories;
+            for _ in 0..signatories.len() {
+                state.active_checkpoint.signatures.push_back(None)?;
+            }
+        }
 
         // Check if this checkpoint should cause a signatory set transition
-        if checkpoint_index % SIGNATORY_CHANGE_INTERVAL == 0 {
+        if checkpoint_index % params.signatory_change_interval == 0 {
             let new_signatories = SignatorySetSnapshot {
                 time: now,
                 signatories: signatories_from_validators(validators)?,
+                recovery: state.recovery.get_or_default()?,
             };
 
             state
@@ -193,27 +469,136 @@ pub fn begin_block<S: Store>(
                 .next_signatory_set
                 .set(Some(new_signatories))?;
         }
+
+        state.record_active_checkpoint()?;
     }
 
     Ok(())
 }
 
-pub fn header_tx<S: Store>(state: &mut PegState<S>, tx: HeaderTransaction) -> Result<()> {
-    let mut header_cache = HeaderCache::new(bitcoin_network, &mut state.headers);
-    for header in tx.block_headers {
-        header_cache.add_header(&header)?;
+/// Leaves the outputs of a signatory set out of checkpoints after it failed to
+/// sign one in time, for the `checkpoint_signing_timeout` parameter doubled
+/// with each failure in a row, up to 32 times.
+fn back_off_signatory_set<S: Store>(state: &mut PegState<S>, index: u64, now: u64) -> Result<()> {
+    let failures = match state.signing_backoffs.get(index)? {
+        Some(backoff) => backoff.failures + 1,
+        None => 1,
+    };
+    let duration = state
+        .params()?
+        .checkpoint_signing_timeout
+        .saturating_mul(1 << (failures - 1).min(5));
+    warn!(
+        "signatory set {} failed to sign, skipping its outputs for {} seconds",
+        index, duration
+    );
+    state.signing_backoffs.insert(
+        index,
+        SigningBackoff {
+            failures,
+            until: now.saturating_add(duration),
+        },
+    )
+}
+
+/// Aborts the active checkpoint, either because it failed to collect enough
+/// signatures within the `checkpoint_signing_timeout` parameter or because one
+/// of its inputs was reorged out. Its withdrawals go back to the front of the
+/// pending queue to be retried by the next checkpoint. Its UTXOs go to the
+/// back, and signatory sets which held it up are backed off by the caller.
+fn abort_checkpoint<S: Store>(state: &mut PegState<S>) -> Result<()> {
+    let checkpoint_index = state.checkpoint_index.get_or_default()?;
+    warn!("aborting checkpoint {}", checkpoint_index);
+
+    let mut record = state.current_checkpoint_record()?;
+    record.status = CheckpointStatus::Aborted;
+    state.checkpoints.insert(checkpoint_index, record)?;
+
+    for utxo in state.active_utxos()? {
+        state.utxos.push_back(utxo)?;
+    }
+    let withdrawals = state
+        .active_checkpoint
+        .withdrawals
+        .iter()
+        .collect::<Result<Vec<_>>>()?;
+    for withdrawal in withdrawals.into_iter().rev() {
+        state.pending_withdrawals.push_front(withdrawal)?;
+    }
+
+    state.active_checkpoint.utxos.clear()?;
+    state.active_checkpoint.withdrawals.clear()?;
+    state.active_checkpoint.signatures.clear()?;
+    state.active_checkpoint.signed_voting_power.set(0)?;
+    state.active_checkpoint.next_signatory_set.set(None)?;
+    state.active_checkpoint.is_active.set(false)?;
+
+    Ok(())
+}
+
+/// Starts a new signatory set from the current validators, unless it would be
+/// the same as the current set. New deposits pay to the new set right away,
+/// while the reserve moves to it with the next checkpoint's change output.
+pub fn rotate_signatories<S: Store>(
+    state: &mut PegState<S>,
+    validators: &BTreeMap<Vec<u8>, u64>,
+    now: u64,
+) -> Result<()> {
+    let signatories = signatories_from_validators(validators)?;
+    if let Some(current) = state.signatory_sets.back()? {
+        if current.signatories == signatories {
+            return Ok(());
+        }
     }
+    state.signatory_sets.push_back(SignatorySetSnapshot {
+        time: now,
+        signatories,
+        recovery: state.recovery.get_or_default()?,
+    })?;
     Ok(())
 }
 
+/// Adds Bitcoin headers to the SPV chain and credits the deposits they
+/// confirm. Returns an event for each deposit unwound by a reorg.
+pub fn header_tx<S: Store>(
+    state: &mut PegState<S>,
+    account_state: &mut AccountState<S>,
+    tx: HeaderTransaction,
+) -> Result<Vec<Event>> {
+    let network = state.network()?;
+    let now = state.block_time.get_or_default()?;
+    let mut header_cache = HeaderCache::new(network, &mut state.headers);
+    header_cache.set_time(now);
+    let mut unwound = vec![];
+    for header in tx.block_headers {
+        if let Some((_, Some(unwinds), _)) = header_cache.add_header(&header)? {
+            unwound.extend(unwinds.into_iter().map(|hash| hash.into_inner()));
+        }
+    }
+
+    let events = if unwound.is_empty() {
+        vec![]
+    } else {
+        unwind_deposits(state, &unwound)?
+    };
+    process_pending_deposits(state, account_state)?;
+    Ok(events)
+}
+
 pub fn withdrawal_tx<S: Store>(
     state: &mut PegState<S>,
     account_state: &mut AccountState<S>,
     tx: WithdrawalTransaction,
 ) -> Result<()> {
+    if state.is_paused()? {
+        bail!("Peg is paused");
+    }
     if tx.from.len() != 33 {
         bail!("Invalid sender address");
     }
+    if !is_standard_withdrawal_script(&tx.to) {
+        bail!("Withdrawal script must be P2PKH, P2SH, P2WPKH or P2WSH");
+    }
     let maybe_sender_account = account_state.get(unsafe_slice_to_address(&tx.from[..]))?;
     let mut sender_account = match maybe_sender_account {
         Some(sender_account) => sender_account,
@@ -233,15 +618,29 @@ pub fn withdrawal_tx<S: Store>(
         bail!("Invalid signature");
     }
 
+    // The withdrawer pays for their output's share of the checkpoint weight,
+    // which is deducted from the amount paid out
+    let fee = state.withdrawal_fee(&tx.to)?;
+    let value = match tx.amount.checked_sub(fee) {
+        Some(value) => value,
+        None => bail!("Withdrawal amount does not cover fee"),
+    };
+    if value < dust_limit(&tx.to) {
+        bail!("Withdrawal output is below the dust limit");
+    }
+
     sender_account.nonce += 1;
 
     sender_account.balance -= tx.amount;
     account_state.insert(unsafe_slice_to_address(&tx.from[..]), sender_account)?;
+    account_state.burn(tx.amount)?;
+    let withdrawal_fees = state.withdrawal_fees.get_or_default()?;
+    state.withdrawal_fees.set(withdrawal_fees + fee)?;
 
     use crate::core::bitcoin::Script;
     // Push withdrawal to pending withdrawals deque
     let withdrawal = Withdrawal {
-        value: tx.amount,
+        value,
         script: Script(tx.to),
     };
     Ok(state.pending_withdrawals.push_back(withdrawal)?)
@@ -252,7 +651,21 @@ pub fn signature_tx<S: Store>(state: &mut PegState<S>, tx: SignatureTransaction)
         bail!("No checkpoint in progress");
     }
 
-    if tx.signatures.len() != state.active_utxos()?.len() {
+    // Signatures only cover the inputs paying to the given signatory set
+    let utxos = state.active_utxos()?;
+    let set_indexes = input_signatory_set_indexes(&utxos);
+    let signatory_set_index = tx.signatory_set_index;
+    if !set_indexes.contains(&signatory_set_index) {
+        bail!("Checkpoint does not spend from signatory set");
+    }
+    let input_indexes: Vec<usize> = utxos
+        .iter()
+        .enumerate()
+        .filter(|(_, utxo)| utxo.signatory_set_index == signatory_set_index)
+        .map(|(i, _)| i)
+        .collect();
+
+    if tx.signatures.len() != input_indexes.len() {
         bail!("Number of signatures does not match number of inputs");
     }
     let sigs: Vec<_> = tx
@@ -271,19 +684,13 @@ pub fn signature_tx<S: Store>(state: &mut PegState<S>, tx: SignatureTransaction)
     let btc_tx = state.active_checkpoint_tx()?;
     info!("received signature for btc_tx: {:?}", &btc_tx);
 
-    let signatory_set_index = state.active_checkpoint.signatory_set_index.get()?;
-    let signatories = state
-        .signatory_sets
-        .get_fixed(signatory_set_index)?
-        .signatories;
+    let snapshot = state.signatory_sets.get_fixed(signatory_set_index)?;
+    let signatories = &snapshot.signatories;
     if signatory_index as usize >= signatories.len() {
         bail!("Signatory index out of bounds");
     }
-    if let Some(_) = state
-        .active_checkpoint
-        .signatures
-        .get(signatory_index as u64)?
-    {
+    let slot = state.signature_offset(&set_indexes, signatory_set_index)? + signatory_index as u64;
+    if let Some(_) = state.active_checkpoint.signatures.get(slot)? {
         bail!("Signatory has already signed");
     }
     let signatory = signatories
@@ -294,14 +701,9 @@ pub fn signature_tx<S: Store>(state: &mut PegState<S>, tx: SignatureTransaction)
     let pubkey = signatory.pubkey.key;
 
     // Verify signatures
-    for (i, signature) in sigs.iter().enumerate() {
-        let utxo = state.active_checkpoint.utxos.get(i as u64)?;
-        let signatories = state
-            .signatory_sets
-            .get_fixed(utxo.signatory_set_index)?
-            .signatories;
-
-        let script = crate::core::signatory_set::redeem_script(&signatories, utxo.data);
+    for (signature, i) in sigs.iter().zip(input_indexes) {
+        let utxo = &utxos[i];
+        let script = snapshot.redeem_script(utxo.data.clone());
         let sighash = bitcoin::util::bip143::SighashComponents::new(&btc_tx).sighash_all(
             &btc_tx.input[i],
             &script,
@@ -320,20 +722,35 @@ pub fn signature_tx<S: Store>(state: &mut PegState<S>, tx: SignatureTransaction)
         .get_or_default()?;
     signed_voting_power += signatory.voting_power;
 
-    state
-        .active_checkpoint
-        .signatures
-        .set(signatory_index as u64, Some(sigs))?;
+    let mut record = state.current_checkpoint_record()?;
+    record.signatures[slot as usize] = Some(sigs.iter().map(|sig| sig.to_vec()).collect());
+    state.active_checkpoint.signatures.set(slot, Some(sigs))?;
 
-    // If >2/3, finalize checkpoint, clear active_checkpoint fields, update last checkpoint time
-    if signed_voting_power as u128 > signatories.two_thirds_voting_power() {
-        if let Some(new_signatories) = state
+    // If >2/3 of every spent set has signed, finalize checkpoint, clear active_checkpoint fields
+    if state.active_checkpoint_is_signed()? {
+        // sets which were backed off start over once they sign again
+        for index in set_indexes.iter() {
+            if state.signing_backoffs.get(*index)?.is_some() {
+                state.signing_backoffs.insert(*index, Default::default())?;
+            }
+        }
+
+        // The change pays to the next signatory set if there is one, or else to
+        // the set the checkpoint was built for, even if a newer set has been
+        // pushed by a rotation while it was being signed
+        let change_set_index = match state
             .active_checkpoint
             .next_signatory_set
             .get_or_default()?
         {
-            state.signatory_sets.push_back(new_signatories)?;
-        }
+            Some(new_signatories) => {
+                state.signatory_sets.push_back(new_signatories)?;
+                state
+                    .signatory_sets
+                    .fixed_index(state.signatory_sets.len() - 1)
+            }
+            None => state.active_checkpoint.signatory_set_index.get()?,
+        };
 
         state.finalized_checkpoint.utxos.clear()?;
         state.finalized_checkpoint.withdrawals.clear()?;
@@ -366,6 +783,13 @@ pub fn signature_tx<S: Store>(state: &mut PegState<S>, tx: SignatureTransaction)
                 .get_or_default()?,
         )?;
         state.active_checkpoint.next_signatory_set.set(None)?;
+        state
+            .last_refresh_time
+            .set(state.active_checkpoint.start_time.get_or_default()?)?;
+        state
+            .finalized_checkpoint
+            .fee_rate
+            .set(state.active_checkpoint.fee_rate.get_or_default()?)?;
 
         state.utxos.push_back(Utxo {
             outpoint: crate::core::bitcoin::Outpoint {
@@ -373,16 +797,23 @@ pub fn signature_tx<S: Store>(state: &mut PegState<S>, tx: SignatureTransaction)
                 index: btc_tx.output.len() as u32 - 1,
             },
             value: btc_tx.output.last().unwrap().value,
-            signatory_set_index: state
-                .signatory_sets
-                .fixed_index(state.signatory_sets.len() - 1),
+            signatory_set_index: change_set_index,
             data: vec![],
         })?;
 
+        let input_value: u64 = utxos.iter().map(|utxo| utxo.value).sum();
+        let output_value: u64 = btc_tx.output.iter().map(|output| output.value).sum();
+        let checkpoint_fees = state.checkpoint_fees.get_or_default()?;
+        state
+            .checkpoint_fees
+            .set(checkpoint_fees + input_value - output_value)?;
+
         let mut raw_tx = vec![];
         state
             .finalized_checkpoint_tx()?
             .consensus_encode(&mut raw_tx)?;
+        record.status = CheckpointStatus::Finalized;
+        record.tx = Some(raw_tx.clone());
         state.finalized_checkpoint_txs.push_back(raw_tx)?;
     } else {
         state
@@ -391,6 +822,69 @@ pub fn signature_tx<S: Store>(state: &mut PegState<S>, tx: SignatureTransaction)
             .set(signed_voting_power)?;
     }
 
+    let checkpoint_index = state.checkpoint_index.get_or_default()?;
+    state.checkpoints.insert(checkpoint_index, record)?;
+
+    Ok(())
+}
+
+/// Records a validator's signal to pause or resume the peg. Once the validators
+/// signaling in the current round hold more than the `pause_threshold`
+/// percentage of voting power, the peg is paused or resumed and a new round
+/// starts.
+pub fn pause_tx<S: Store>(
+    state: &mut PegState<S>,
+    validators: &BTreeMap<Vec<u8>, u64>,
+    tx: PauseTransaction,
+) -> Result<()> {
+    match validators.get(&tx.validator) {
+        Some(voting_power) if *voting_power > 0 => {}
+        _ => bail!("Sender is not a validator"),
+    }
+    if tx.validator.len() != 33 {
+        bail!("Invalid validator public key");
+    }
+    if !tx.verify_signature(&SECP)? {
+        bail!("Invalid signature");
+    }
+
+    let round = state.pause_round.get_or_default()?;
+    if tx.round != round {
+        bail!("Invalid pause round");
+    }
+    if tx.paused == state.is_paused()? {
+        if tx.paused {
+            bail!("Peg is already paused");
+        } else {
+            bail!("Peg is not paused");
+        }
+    }
+    let validator = unsafe_slice_to_address(tx.validator.as_slice());
+    if state.pause_signals.get(validator)? == Some(round) {
+        bail!("Validator has already signaled");
+    }
+    state.pause_signals.insert(validator, round)?;
+
+    let mut signaled_voting_power: u64 = 0;
+    for (key, voting_power) in validators.iter() {
+        if key.len() != 33 {
+            continue;
+        }
+        let key = unsafe_slice_to_address(key.as_slice());
+        if state.pause_signals.get(key)? == Some(round) {
+            signaled_voting_power += voting_power;
+        }
+    }
+    let total_voting_power: u64 = validators.values().sum();
+    let threshold = state.params()?.pause_threshold;
+    if signaled_voting_power as u128 * 100 > total_voting_power as u128 * threshold as u128 {
+        warn!(
+            "validators signaled to {} the peg",
+            if tx.paused { "pause" } else { "resume" }
+        );
+        state.set_paused(tx.paused)?;
+    }
+
     Ok(())
 }
 
@@ -400,6 +894,9 @@ pub fn signatories_from_validators(validators: &BTreeMap<Vec<u8>, u64>) -> Resul
         let key = bitcoin::PublicKey::from_slice(key_bytes.as_slice())?;
         signatories.set(Signatory::new(key, *voting_power));
     }
+    // Keep the reserve script within script limits, thresholds are computed
+    // over the selected signatories only
+    signatories.truncate(MAX_SIGNATORIES);
     Ok(signatories)
 }
 
@@ -423,10 +920,14 @@ mod tests {
     use super::*;
 
     use super::super::super::spv::headercache::HeaderCache;
-    use crate::core::bitcoin::bitcoin;
+    use super::super::{
+        CHECKPOINT_INTERVAL, CHECKPOINT_SIGNING_TIMEOUT, DEPOSIT_CONFIRMATION_DEPTH,
+        DEPOSIT_SIGNATORY_SET_WINDOW, MAX_CHECKPOINT_INPUTS, MAX_CHECKPOINT_WEIGHT,
+        SIGNATORY_CHANGE_INTERVAL,
+    };
+    use crate::core::bitcoin::{bitcoin, SpvCheckpoint};
     use crate::core::primitives::Account;
-    use crate::core::signatory_set::{Signatory, SignatorySet, SignatorySetSnapshot};
-    use bitcoin::Network::Testnet as bitcoin_network;
+    use crate::core::signatory_set::{Recovery, Signatory, SignatorySet, SignatorySetSnapshot};
     use lazy_static::lazy_static;
     use orga::{abci::messages::Header as TendermintHeader, MapStore, WrapStore};
     use protobuf::well_known_types::Timestamp;
@@ -437,14 +938,23 @@ mod tests {
     lazy_static! {
         pub static ref SECP: Secp256k1<SignOnly> = Secp256k1::signing_only();
     }
+
+    fn genesis(network: Network) -> Genesis {
+        Genesis {
+            network,
+            ..Default::default()
+        }
+    }
+
     #[test]
     fn init() {
         let mut store = MapStore::new();
         let mut state = PegState::wrap_store(&mut store).unwrap();
-        let chkpt = get_checkpoint_header();
-        super::initialize(&mut state).unwrap();
+        let chkpt = get_checkpoint_header(Network::Testnet);
+        super::initialize(&mut state, &genesis(Network::Testnet)).unwrap();
+        assert_eq!(state.network().unwrap(), Network::Testnet);
 
-        let mut header_cache = HeaderCache::new(bitcoin_network, &mut state.headers);
+        let mut header_cache = HeaderCache::new(Network::Testnet, &mut state.headers);
         let header = header_cache
             .get_header_for_height(chkpt.height)
             .unwrap()
@@ -452,6 +962,57 @@ mod tests {
         assert_eq!(header.stored.header, chkpt.header);
     }
 
+    #[test]
+    fn init_regtest() {
+        let mut store = MapStore::new();
+        let mut state = PegState::wrap_store(&mut store).unwrap();
+        super::initialize(&mut state, &genesis(Network::Regtest)).unwrap();
+        assert_eq!(state.network().unwrap(), Network::Regtest);
+
+        let mut header_cache = HeaderCache::new(Network::Regtest, &mut state.headers);
+        let header = header_cache.get_header_for_height(0).unwrap().unwrap();
+        assert_eq!(header.stored.header, Network::Regtest.genesis_header());
+    }
+
+    #[test]
+    fn init_from_checkpoint() {
+        let mut store = MapStore::new();
+        let mut state = PegState::wrap_store(&mut store).unwrap();
+        let block = empty_block();
+        let checkpoint = SpvCheckpoint {
+            height: 5000,
+            header: block.header,
+            chainwork: "1234".to_string(),
+        };
+        let genesis = Genesis {
+            checkpoint: Some(checkpoint),
+            ..genesis(Network::Regtest)
+        };
+        super::initialize(&mut state, &genesis).unwrap();
+
+        let mut header_cache = HeaderCache::new(Network::Regtest, &mut state.headers);
+        assert!(header_cache.get_header_for_height(0).unwrap().is_none());
+        let header = header_cache.get_header_for_height(5000).unwrap().unwrap();
+        assert_eq!(header.stored.header, block.header);
+        assert_eq!(header.stored.work_bytes, [0x1234, 0, 0, 0]);
+    }
+
+    #[test]
+    fn signatories_from_validators_capped() {
+        let mut validators = BTreeMap::new();
+        for i in 1..=MAX_SIGNATORIES as u8 + 10 {
+            let (_, pubkey) = create_keypair(i);
+            validators.insert(pubkey.serialize().to_vec(), i as u64 * 100);
+        }
+
+        let signatories = signatories_from_validators(&validators).unwrap();
+        assert_eq!(signatories.len(), MAX_SIGNATORIES);
+        // the 10 validators with the least voting power are left out
+        assert_eq!(signatories.iter().last().unwrap().voting_power, 1_100);
+        let total: u64 = (11..=MAX_SIGNATORIES as u64 + 10).map(|i| i * 100).sum();
+        assert_eq!(signatories.total_voting_power(), total as u128);
+    }
+
     #[test]
     fn begin_block() {
         let mut net = MockNet::with_active_checkpoint();
@@ -477,7 +1038,8 @@ mod tests {
                 state.current_signatory_set().unwrap(),
                 SignatorySetSnapshot {
                     time: 0,
-                    signatories: expected_signatories
+                    signatories: expected_signatories,
+                    recovery: None,
                 }
             );
 
@@ -485,16 +1047,12 @@ mod tests {
         }
 
         const TEST_START_TIME: u64 = 100_000_000;
-        let mut time = TEST_START_TIME as i64;
+        let mut time = TEST_START_TIME;
         let mut next_checkpoint = |net: &mut MockNet| {
             let mut state = PegState::wrap_store(&mut net.store).unwrap();
 
-            let mut header: TendermintHeader = Default::default();
-            let mut timestamp = Timestamp::new();
-            timestamp.set_seconds(time);
-            time += CHECKPOINT_INTERVAL as i64 + 1;
-            header.set_time(timestamp);
-            super::begin_block(&mut state, &mut net.validators, header).unwrap();
+            begin_block_at(&mut state, &net.validators, time);
+            time += CHECKPOINT_INTERVAL + 1;
 
             signatory_sign(&mut state, &old_val_privkey);
         };
@@ -522,7 +1080,8 @@ mod tests {
             get_state(&mut net).current_signatory_set().unwrap(),
             SignatorySetSnapshot {
                 time: 0,
-                signatories: expected_signatories.clone()
+                signatories: expected_signatories.clone(),
+                recovery: None,
             }
         );
 
@@ -540,7 +1099,8 @@ mod tests {
             get_state(&mut net).current_signatory_set().unwrap(),
             SignatorySetSnapshot {
                 time: TEST_START_TIME + (SIGNATORY_CHANGE_INTERVAL - 2) * (CHECKPOINT_INTERVAL + 1),
-                signatories: expected_signatories
+                signatories: expected_signatories,
+                recovery: None,
             }
         );
     }
@@ -608,13 +1168,7 @@ mod tests {
     #[test]
     #[should_panic(expected = "Transaction was already processed")]
     fn deposit_duplicate() {
-        let tx = build_tx(vec![build_txout(
-            100_000_000,
-            crate::core::signatory_set::output_script(
-                &signatories_from_validators(&mock_validator_set().0).unwrap(),
-                vec![123; 33],
-            ),
-        )]);
+        let tx = mock_deposit();
         let block = build_block(vec![tx.clone()]);
         let mut net = MockNet::with_btc_block(block);
 
@@ -637,13 +1191,7 @@ mod tests {
     #[test]
     #[should_panic(expected = "Consumed all recipients")]
     fn deposit_no_recipients() {
-        let tx = build_tx(vec![build_txout(
-            100_000_000,
-            crate::core::signatory_set::output_script(
-                &signatories_from_validators(&mock_validator_set().0).unwrap(),
-                vec![123; 33],
-            ),
-        )]);
+        let tx = mock_deposit();
         let block = build_block(vec![tx.clone()]);
         let mut net = MockNet::with_btc_block(block);
 
@@ -663,13 +1211,7 @@ mod tests {
 
     #[test]
     fn deposit_ok() {
-        let tx = build_tx(vec![build_txout(
-            100_000_000,
-            crate::core::signatory_set::output_script(
-                &signatories_from_validators(&mock_validator_set().0).unwrap(),
-                vec![123; 33],
-            ),
-        )]);
+        let tx = mock_deposit();
 
         let block = build_block(vec![tx.clone()]);
         let mut net = MockNet::with_btc_block(block);
@@ -694,38 +1236,748 @@ mod tests {
                 nonce: 0
             }
         );
+        assert_eq!(account_state.supply().unwrap(), 100_000_000);
+    }
+
+    fn rotate_signatory_set(state: &mut PegState<&mut MapStore>, key_byte: u8) {
+        let (_, pubkey) = create_keypair(key_byte);
+        let mut signatories = SignatorySet::new();
+        signatories.set(Signatory::new(
+            bitcoin::PublicKey {
+                key: pubkey,
+                compressed: true,
+            },
+            100,
+        ));
+        state
+            .signatory_sets
+            .push_back(SignatorySetSnapshot {
+                time: key_byte as u64,
+                signatories,
+                recovery: None,
+            })
+            .unwrap();
     }
 
     #[test]
-    fn withdrawal_ok() {
-        let mut net = MockNet::new();
+    fn deposit_to_previous_signatory_set() {
+        let tx = mock_deposit();
+
+        let block = build_block(vec![tx.clone()]);
+        let mut net = MockNet::with_btc_block(block);
+        let (tx, proof) = net.create_btc_proof();
         let mut peg_state = PegState::wrap_store(&mut net.store).unwrap();
         let mut account_state = AccountState::wrap_store(&mut net.store2).unwrap();
 
-        let sender = create_sender(&mut account_state, 1234, 0);
+        rotate_signatory_set(&mut peg_state, 2);
 
-        let mut tx = WithdrawalTransaction {
-            from: sender.address.clone(),
-            to: bitcoin::Script::from(vec![123]),
-            amount: 1000,
-            signature: vec![],
-            nonce: 0,
+        let deposit = DepositTransaction {
+            height: 0,
+            proof,
+            tx,
+            block_index: 0,
+            recipients: vec![vec![123; 33]],
         };
-        let sig = sign(&mut tx, sender.privkey);
-        tx.signature = sig;
-        withdrawal_tx(&mut peg_state, &mut account_state, tx).unwrap();
 
+        deposit_tx(&mut peg_state, &mut account_state, deposit).unwrap();
         assert_eq!(
-            account_state
-                .get(unsafe_slice_to_address(&sender.address[..]))
-                .unwrap()
-                .unwrap(),
-            Account {
-                balance: 234,
-                nonce: 1,
-            }
+            account_state.get([123; 33]).unwrap().unwrap().balance,
+            100_000_000
         );
-        assert_eq!(peg_state.pending_withdrawals.get(0).unwrap().value, 1000);
+        assert_eq!(peg_state.utxos.get(0).unwrap().signatory_set_index, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Transaction does not contain any deposit outputs")]
+    fn deposit_to_expired_signatory_set() {
+        let tx = mock_deposit();
+
+        let block = build_block(vec![tx.clone()]);
+        let mut net = MockNet::with_btc_block(block);
+        let (tx, proof) = net.create_btc_proof();
+        let mut peg_state = PegState::wrap_store(&mut net.store).unwrap();
+        let mut account_state = AccountState::wrap_store(&mut net.store2).unwrap();
+
+        for i in 0..DEPOSIT_SIGNATORY_SET_WINDOW {
+            rotate_signatory_set(&mut peg_state, 2 + i as u8);
+        }
+
+        let deposit = DepositTransaction {
+            height: 0,
+            proof,
+            tx,
+            block_index: 0,
+            recipients: vec![vec![123; 33]],
+        };
+
+        deposit_tx(&mut peg_state, &mut account_state, deposit).unwrap();
+    }
+
+    #[test]
+    fn deposit_signatory_set_window_param() {
+        let tx = mock_deposit();
+
+        let block = build_block(vec![tx.clone()]);
+        let mut net = MockNet::with_btc_block(block);
+        let (tx, proof) = net.create_btc_proof();
+        let mut peg_state = PegState::wrap_store(&mut net.store).unwrap();
+        let mut account_state = AccountState::wrap_store(&mut net.store2).unwrap();
+        peg_state
+            .params
+            .set(super::super::Params {
+                deposit_signatory_set_window: DEPOSIT_SIGNATORY_SET_WINDOW + 1,
+                ..Default::default()
+            })
+            .unwrap();
+
+        // past the default window, but within the configured one
+        for i in 0..DEPOSIT_SIGNATORY_SET_WINDOW {
+            rotate_signatory_set(&mut peg_state, 2 + i as u8);
+        }
+
+        let deposit = DepositTransaction {
+            height: 0,
+            proof,
+            tx,
+            block_index: 0,
+            recipients: vec![vec![123; 33]],
+        };
+
+        deposit_tx(&mut peg_state, &mut account_state, deposit).unwrap();
+        assert_eq!(peg_state.utxos.get(0).unwrap().signatory_set_index, 0);
+    }
+
+    #[test]
+    fn deposit_pending_until_confirmed() {
+        let tx = mock_deposit();
+
+        let block = build_block(vec![tx.clone()]);
+        let mut net = MockNet::with_confirmations(block, 0);
+        let (tx, proof) = net.create_btc_proof();
+        let headers = mine_headers(&net.btc_block.header, DEPOSIT_CONFIRMATION_DEPTH as usize);
+        let mut peg_state = PegState::wrap_store(&mut net.store).unwrap();
+        let mut account_state = AccountState::wrap_store(&mut net.store2).unwrap();
+
+        let deposit = DepositTransaction {
+            height: 0,
+            proof,
+            tx,
+            block_index: 0,
+            recipients: vec![vec![123; 33]],
+        };
+        deposit_tx(&mut peg_state, &mut account_state, deposit).unwrap();
+
+        // not credited yet
+        assert!(account_state.get([123; 33]).unwrap().is_none());
+        assert_eq!(peg_state.pending_deposits.len(), 1);
+        assert_eq!(peg_state.utxos.len(), 0);
+
+        // one header short of the confirmation depth
+        let (last_header, headers) = headers.split_last().unwrap();
+        let tx = HeaderTransaction {
+            block_headers: headers.to_vec(),
+        };
+        header_tx(&mut peg_state, &mut account_state, tx).unwrap();
+        assert!(account_state.get([123; 33]).unwrap().is_none());
+        assert_eq!(peg_state.pending_deposits.len(), 1);
+
+        let tx = HeaderTransaction {
+            block_headers: vec![*last_header],
+        };
+        header_tx(&mut peg_state, &mut account_state, tx).unwrap();
+        assert_eq!(
+            account_state.get([123; 33]).unwrap().unwrap(),
+            Account {
+                balance: 100_000_000,
+                nonce: 0
+            }
+        );
+        assert_eq!(peg_state.pending_deposits.len(), 0);
+        assert_eq!(peg_state.utxos.len(), 1);
+    }
+
+    #[test]
+    fn headers_past_retarget_boundary() {
+        let mut net = MockNet::with_confirmations(empty_block(), 0);
+        let headers = mine_headers(&net.btc_block.header, 2100);
+        let mut peg_state = PegState::wrap_store(&mut net.store).unwrap();
+        let mut account_state = AccountState::wrap_store(&mut net.store2).unwrap();
+
+        for batch in headers.chunks(100) {
+            let tx = HeaderTransaction {
+                block_headers: batch.to_vec(),
+            };
+            header_tx(&mut peg_state, &mut account_state, tx).unwrap();
+        }
+
+        let mut header_cache = HeaderCache::new(Network::Regtest, &mut peg_state.headers);
+        let tip = header_cache.tip().unwrap().unwrap();
+        assert_eq!(tip.stored.height, 2100);
+        assert_eq!(tip.stored.header, headers[2099]);
+    }
+
+    #[test]
+    fn header_from_the_future() {
+        let mut net = MockNet::with_confirmations(empty_block(), 0);
+        let mut header = mine_headers(&net.btc_block.header, 1)[0];
+        let mut peg_state = PegState::wrap_store(&mut net.store).unwrap();
+        let mut account_state = AccountState::wrap_store(&mut net.store2).unwrap();
+
+        // the mock net's Tendermint time is 0
+        header.time = 2 * 60 * 60 + 1;
+        while header.validate_pow(&header.target()).is_err() {
+            header.nonce += 1;
+        }
+        let tx = HeaderTransaction {
+            block_headers: vec![header],
+        };
+        let err = header_tx(&mut peg_state, &mut account_state, tx).unwrap_err();
+        assert_eq!(err.to_string(), "header time is too far in the future");
+    }
+
+    /// Builds a transaction depositing 1 BTC to the mock validators' signatory
+    /// set for the account `[123; 33]`.
+    fn mock_deposit() -> bitcoin::Transaction {
+        build_tx(vec![build_txout(
+            100_000_000,
+            crate::core::signatory_set::output_script(
+                &signatories_from_validators(&mock_validator_set().0).unwrap(),
+                vec![123; 33],
+            ),
+        )])
+    }
+
+    fn deposit_block(net: &MockNet) -> bitcoin::Block {
+        mine_block(&net.btc_block.header, vec![mock_deposit()])
+    }
+
+    fn empty_block() -> bitcoin::Block {
+        build_block(vec![build_tx(vec![build_txout(1, vec![].into())])])
+    }
+
+    #[test]
+    fn pending_deposit_reorged_out() {
+        let mut net = MockNet::with_confirmations(empty_block(), 0);
+        let block = deposit_block(&net);
+        let (tx, proof) = create_btc_proof_for(&block, 0);
+        let fork = mine_headers(&net.btc_block.header, 2);
+        let mut peg_state = PegState::wrap_store(&mut net.store).unwrap();
+        let mut account_state = AccountState::wrap_store(&mut net.store2).unwrap();
+
+        let headers = HeaderTransaction {
+            block_headers: vec![block.header],
+        };
+        header_tx(&mut peg_state, &mut account_state, headers).unwrap();
+
+        let deposit = DepositTransaction {
+            height: 1,
+            proof,
+            tx: tx.clone(),
+            block_index: 0,
+            recipients: vec![vec![123; 33]],
+        };
+        deposit_tx(&mut peg_state, &mut account_state, deposit.clone()).unwrap();
+        assert_eq!(peg_state.pending_deposits.len(), 1);
+
+        let headers = HeaderTransaction {
+            block_headers: fork,
+        };
+        let events = header_tx(&mut peg_state, &mut account_state, headers).unwrap();
+
+        let txid = tx.txid().to_string();
+        assert_eq!(
+            events,
+            vec![Event {
+                kind: "dropped_deposit".to_string(),
+                attributes: vec![
+                    ("txid".to_string(), txid.clone()),
+                    ("outpoint".to_string(), format!("{}:0", txid)),
+                    ("amount".to_string(), "100000000".to_string()),
+                ],
+            }]
+        );
+        assert_eq!(peg_state.pending_deposits.len(), 0);
+        assert_eq!(peg_state.reorged_deposits.len(), 0);
+        assert!(!peg_state
+            .processed_deposit_txids
+            .contains(tx.txid().as_hash().into_inner())
+            .unwrap());
+        assert!(account_state.get([123; 33]).unwrap().is_none());
+
+        // the deposit can be proven again, but not against the old block
+        let err = deposit_tx(&mut peg_state, &mut account_state, deposit).unwrap_err();
+        assert_eq!(err.to_string(), "Proof merkle root does not match chain");
+    }
+
+    #[test]
+    fn credited_deposit_reorged_out() {
+        let mut net = MockNet::with_confirmations(empty_block(), 0);
+        let block = deposit_block(&net);
+        let (tx, proof) = create_btc_proof_for(&block, 0);
+        let mut headers = vec![block.header];
+        headers.extend(mine_headers(
+            &block.header,
+            DEPOSIT_CONFIRMATION_DEPTH as usize,
+        ));
+        let fork = mine_headers(
+            &net.btc_block.header,
+            DEPOSIT_CONFIRMATION_DEPTH as usize + 2,
+        );
+        let mut peg_state = PegState::wrap_store(&mut net.store).unwrap();
+        let mut account_state = AccountState::wrap_store(&mut net.store2).unwrap();
+
+        let headers = HeaderTransaction {
+            block_headers: headers,
+        };
+        header_tx(&mut peg_state, &mut account_state, headers).unwrap();
+
+        let deposit = DepositTransaction {
+            height: 1,
+            proof,
+            tx: tx.clone(),
+            block_index: 0,
+            recipients: vec![vec![123; 33]],
+        };
+        deposit_tx(&mut peg_state, &mut account_state, deposit).unwrap();
+        assert_eq!(peg_state.recent_deposits.len(), 1);
+        assert_eq!(peg_state.reorged_deposits.len(), 0);
+
+        let headers = HeaderTransaction {
+            block_headers: fork,
+        };
+        let events = header_tx(&mut peg_state, &mut account_state, headers).unwrap();
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, "reorged_deposit");
+        assert_eq!(
+            events[0].attributes[0],
+            ("txid".to_string(), tx.txid().to_string())
+        );
+        assert_eq!(
+            events[0].attributes[3],
+            ("status".to_string(), "Removed".to_string())
+        );
+
+        assert_eq!(peg_state.recent_deposits.len(), 0);
+        assert_eq!(peg_state.reorged_deposits.len(), 1);
+        let reorged = peg_state.reorged_deposits.get(0).unwrap();
+        assert_eq!(
+            reorged.deposit.block_hash,
+            block.header.bitcoin_hash().into_inner()
+        );
+        assert_eq!(reorged.deposit.utxo.value, 100_000_000);
+        assert_eq!(reorged.status, ReorgedUtxoStatus::Removed);
+        assert!(peg_state.pending_utxos().unwrap().is_empty());
+    }
+
+    #[test]
+    fn checkpointed_deposit_reorged_out() {
+        let mut net = MockNet::with_confirmations(empty_block(), 0);
+        let block = deposit_block(&net);
+        let (tx, proof) = create_btc_proof_for(&block, 0);
+        let mut headers = vec![block.header];
+        headers.extend(mine_headers(
+            &block.header,
+            DEPOSIT_CONFIRMATION_DEPTH as usize,
+        ));
+        let fork = mine_headers(
+            &net.btc_block.header,
+            DEPOSIT_CONFIRMATION_DEPTH as usize + 2,
+        );
+        let validators = net.validators.clone();
+        let mut peg_state = PegState::wrap_store(&mut net.store).unwrap();
+        let mut account_state = AccountState::wrap_store(&mut net.store2).unwrap();
+
+        let headers = HeaderTransaction {
+            block_headers: headers,
+        };
+        header_tx(&mut peg_state, &mut account_state, headers).unwrap();
+        let deposit = DepositTransaction {
+            height: 1,
+            proof,
+            tx,
+            block_index: 0,
+            recipients: vec![vec![123; 33]],
+        };
+        deposit_tx(&mut peg_state, &mut account_state, deposit).unwrap();
+
+        begin_block_at(&mut peg_state, &validators, CHECKPOINT_INTERVAL * 2);
+        assert!(peg_state.active_checkpoint.is_active.get().unwrap());
+
+        let headers = HeaderTransaction {
+            block_headers: fork,
+        };
+        header_tx(&mut peg_state, &mut account_state, headers).unwrap();
+
+        assert!(!peg_state.active_checkpoint.is_active.get().unwrap());
+        assert!(peg_state.pending_utxos().unwrap().is_empty());
+        let record = peg_state.checkpoints.get(1).unwrap().unwrap();
+        assert_eq!(record.status, CheckpointStatus::Aborted);
+        let reorged = peg_state.reorged_deposits.get(0).unwrap();
+        assert_eq!(reorged.status, ReorgedUtxoStatus::CheckpointAborted);
+        assert_eq!(reorged.time, CHECKPOINT_INTERVAL * 2);
+    }
+
+    #[test]
+    fn withdrawal_ok() {
+        let mut net = MockNet::new();
+        let mut peg_state = PegState::wrap_store(&mut net.store).unwrap();
+        let mut account_state = AccountState::wrap_store(&mut net.store2).unwrap();
+
+        let sender = create_sender(&mut account_state, 1234, 0);
+
+        let mut tx = WithdrawalTransaction {
+            from: sender.address.clone(),
+            to: p2wpkh_script(),
+            amount: 1000,
+            signature: vec![],
+            nonce: 0,
+        };
+        let sig = sign(&mut tx, sender.privkey);
+        tx.signature = sig;
+        withdrawal_tx(&mut peg_state, &mut account_state, tx).unwrap();
+
+        assert_eq!(
+            account_state
+                .get(unsafe_slice_to_address(&sender.address[..]))
+                .unwrap()
+                .unwrap(),
+            Account {
+                balance: 234,
+                nonce: 1,
+            }
+        );
+        assert_eq!(account_state.supply().unwrap(), 234);
+        assert_eq!(
+            peg_state.pending_withdrawals.get(0).unwrap().value,
+            1000 - 31 * super::super::DEFAULT_CHECKPOINT_FEE_RATE
+        );
+        assert_eq!(
+            peg_state.withdrawal_fees.get().unwrap(),
+            31 * super::super::DEFAULT_CHECKPOINT_FEE_RATE
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Peg is paused")]
+    fn deposit_while_paused() {
+        let mut net = MockNet::new();
+        let (tx, proof) = net.create_btc_proof();
+        let mut peg_state = PegState::wrap_store(&mut net.store).unwrap();
+        let mut account_state = AccountState::wrap_store(&mut net.store2).unwrap();
+        peg_state.paused.set(true).unwrap();
+
+        let deposit = DepositTransaction {
+            height: 0,
+            proof,
+            tx,
+            block_index: 0,
+            recipients: vec![vec![123; 33]],
+        };
+        deposit_tx(&mut peg_state, &mut account_state, deposit).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "Peg is paused")]
+    fn withdrawal_while_paused() {
+        let mut net = MockNet::new();
+        let mut peg_state = PegState::wrap_store(&mut net.store).unwrap();
+        let mut account_state = AccountState::wrap_store(&mut net.store2).unwrap();
+        peg_state.paused.set(true).unwrap();
+
+        let sender = create_sender(&mut account_state, 1234, 0);
+        let mut tx = WithdrawalTransaction {
+            from: sender.address.clone(),
+            to: p2wpkh_script(),
+            amount: 1000,
+            signature: vec![],
+            nonce: 0,
+        };
+        tx.signature = sign(&mut tx, sender.privkey);
+        withdrawal_tx(&mut peg_state, &mut account_state, tx).unwrap();
+    }
+
+    #[test]
+    fn no_checkpoint_while_paused() {
+        let mut net = MockNet::with_active_checkpoint();
+        let mut state = PegState::wrap_store(&mut net.store).unwrap();
+        signatory_sign(&mut state, &net.validator_privkeys[0]);
+        state.paused.set(true).unwrap();
+
+        begin_block_at(&mut state, &net.validators, CHECKPOINT_INTERVAL * 5);
+
+        assert_eq!(state.checkpoint_index.get().unwrap(), 1);
+        assert!(!state.active_checkpoint.is_active.get().unwrap());
+        assert_eq!(state.pending_utxos().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn rotate_during_checkpoint() {
+        let mut net = MockNet::with_active_checkpoint();
+        let mut validators = net.validators.clone();
+        validators.insert(create_keypair(2).1.serialize().to_vec(), 50);
+        let mut state = PegState::wrap_store(&mut net.store).unwrap();
+
+        super::rotate_signatories(&mut state, &validators, 10).unwrap();
+        assert_eq!(state.signatory_sets.len(), 2);
+        signatory_sign(&mut state, &net.validator_privkeys[0]);
+
+        // the change still pays to the set the checkpoint was built for
+        let change = state.pending_utxos().unwrap().pop().unwrap();
+        assert_eq!(change.signatory_set_index, 0);
+        let change_script = state
+            .signatory_sets
+            .get_fixed(change.signatory_set_index)
+            .unwrap()
+            .output_script(vec![]);
+        let tx = state.finalized_checkpoint_tx().unwrap();
+        assert_eq!(tx.output.last().unwrap().script_pubkey, change_script);
+    }
+
+    #[test]
+    fn rotate_signatories_from_validators() {
+        let mut net = MockNet::new();
+        let mut state = PegState::wrap_store(&mut net.store).unwrap();
+        assert_eq!(state.signatory_sets.len(), 1);
+
+        // same validators, nothing to rotate
+        super::rotate_signatories(&mut state, &net.validators, 10).unwrap();
+        assert_eq!(state.signatory_sets.len(), 1);
+
+        let mut validators = net.validators.clone();
+        validators.insert(create_keypair(2).1.serialize().to_vec(), 50);
+        super::rotate_signatories(&mut state, &validators, 10).unwrap();
+        assert_eq!(state.signatory_sets.len(), 2);
+        let current = state.current_signatory_set().unwrap();
+        assert_eq!(current.time, 10);
+        assert_eq!(current.signatories.total_voting_power(), 150);
+    }
+
+    /// Builds a pause signal from the validator with key `key_byte`.
+    fn pause_signal(key_byte: u8, paused: bool, round: u64) -> PauseTransaction {
+        let (privkey, pubkey) = create_keypair(key_byte);
+        let mut tx = PauseTransaction {
+            validator: pubkey.serialize().to_vec(),
+            paused,
+            round,
+            signature: vec![],
+        };
+        tx.signature = sign(&mut tx, privkey);
+        tx
+    }
+
+    /// The mock validator with 100 voting power plus two with 50 each.
+    fn pause_validators(net: &MockNet) -> BTreeMap<Vec<u8>, u64> {
+        let mut validators = net.validators.clone();
+        validators.insert(create_keypair(2).1.serialize().to_vec(), 50);
+        validators.insert(create_keypair(3).1.serialize().to_vec(), 50);
+        validators
+    }
+
+    #[test]
+    fn pause_and_resume_by_signals() {
+        let mut net = MockNet::new();
+        let validators = pause_validators(&net);
+        let mut state = PegState::wrap_store(&mut net.store).unwrap();
+
+        pause_tx(&mut state, &validators, pause_signal(2, true, 0)).unwrap();
+        assert!(!state.is_paused().unwrap());
+        pause_tx(&mut state, &validators, pause_signal(3, true, 0)).unwrap();
+        assert!(state.is_paused().unwrap());
+
+        // signals from before the pause can't be replayed
+        assert!(pause_tx(&mut state, &validators, pause_signal(2, true, 0)).is_err());
+        assert!(pause_tx(&mut state, &validators, pause_signal(2, false, 0)).is_err());
+
+        pause_tx(&mut state, &validators, pause_signal(2, false, 1)).unwrap();
+        assert!(state.is_paused().unwrap());
+        pause_tx(&mut state, &validators, pause_signal(1, false, 1)).unwrap();
+        assert!(!state.is_paused().unwrap());
+        assert_eq!(state.pause_round.get().unwrap(), 2);
+    }
+
+    #[test]
+    fn pause_threshold_param() {
+        let mut net = MockNet::new();
+        let validators = pause_validators(&net);
+        let mut state = PegState::wrap_store(&mut net.store).unwrap();
+        state
+            .params
+            .set(super::super::Params {
+                pause_threshold: 60,
+                ..Default::default()
+            })
+            .unwrap();
+
+        pause_tx(&mut state, &validators, pause_signal(1, true, 0)).unwrap();
+        assert!(!state.is_paused().unwrap());
+        pause_tx(&mut state, &validators, pause_signal(2, true, 0)).unwrap();
+        assert!(state.is_paused().unwrap());
+    }
+
+    #[test]
+    #[should_panic(expected = "Validator has already signaled")]
+    fn pause_double_signal() {
+        let mut net = MockNet::new();
+        let validators = pause_validators(&net);
+        let mut state = PegState::wrap_store(&mut net.store).unwrap();
+
+        pause_tx(&mut state, &validators, pause_signal(2, true, 0)).unwrap();
+        pause_tx(&mut state, &validators, pause_signal(2, true, 0)).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "Sender is not a validator")]
+    fn pause_from_non_validator() {
+        let mut net = MockNet::new();
+        let validators = pause_validators(&net);
+        let mut state = PegState::wrap_store(&mut net.store).unwrap();
+
+        pause_tx(&mut state, &validators, pause_signal(9, true, 0)).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "Withdrawal amount does not cover fee")]
+    fn withdrawal_does_not_cover_fee() {
+        let mut net = MockNet::new();
+        let mut peg_state = PegState::wrap_store(&mut net.store).unwrap();
+        let mut account_state = AccountState::wrap_store(&mut net.store2).unwrap();
+
+        let sender = create_sender(&mut account_state, 1234, 0);
+
+        let mut tx = WithdrawalTransaction {
+            from: sender.address.clone(),
+            to: p2wpkh_script(),
+            amount: 300,
+            signature: vec![],
+            nonce: 0,
+        };
+        let sig = sign(&mut tx, sender.privkey);
+        tx.signature = sig;
+        withdrawal_tx(&mut peg_state, &mut account_state, tx).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "Withdrawal output is below the dust limit")]
+    fn withdrawal_dust() {
+        let mut net = MockNet::new();
+        let mut peg_state = PegState::wrap_store(&mut net.store).unwrap();
+        let mut account_state = AccountState::wrap_store(&mut net.store2).unwrap();
+
+        let sender = create_sender(&mut account_state, 1234, 0);
+
+        // 600 sats minus a 310 sat fee leaves 290, below the 294 P2WPKH limit
+        let mut tx = WithdrawalTransaction {
+            from: sender.address.clone(),
+            to: p2wpkh_script(),
+            amount: 600,
+            signature: vec![],
+            nonce: 0,
+        };
+        let sig = sign(&mut tx, sender.privkey);
+        tx.signature = sig;
+        withdrawal_tx(&mut peg_state, &mut account_state, tx).unwrap();
+    }
+
+    #[test]
+    fn dust_limits() {
+        assert_eq!(dust_limit(&p2pkh_script()), 546);
+        assert_eq!(dust_limit(&p2sh_script()), 540);
+        assert_eq!(dust_limit(&p2wpkh_script()), 294);
+        assert_eq!(dust_limit(&p2wsh_script()), 330);
+    }
+
+    #[test]
+    fn standard_withdrawal_scripts() {
+        assert!(is_standard_withdrawal_script(&p2pkh_script()));
+        assert!(is_standard_withdrawal_script(&p2sh_script()));
+        assert!(is_standard_withdrawal_script(&p2wpkh_script()));
+        assert!(is_standard_withdrawal_script(&p2wsh_script()));
+
+        // the same scripts as paid to by addresses from a regtest wallet
+        let regtest_addresses = vec![
+            ("mrmsLMTHRan3SgkoL45xzPbxCebQcF6efz", p2pkh_script()),
+            ("2N4W92anmmvAWxXcAQiQ4D3jphXVb66bhfm", p2sh_script()),
+            (
+                "bcrt1q0dahk7mm0dahk7mm0dahk7mm0dahk7mmv9qyxt",
+                p2wpkh_script(),
+            ),
+            (
+                "bcrt1q0dahk7mm0dahk7mm0dahk7mm0dahk7mm0dahk7mm0dahk7mm0das35pjh7",
+                p2wsh_script(),
+            ),
+        ];
+        for (address, script) in regtest_addresses {
+            let address: bitcoin::Address = address.parse().unwrap();
+            assert!(Network::Regtest.accepts_address(&address));
+            assert_eq!(address.script_pubkey(), script);
+        }
+
+        let nonstandard: Vec<Vec<u8>> = vec![
+            vec![],
+            vec![123],
+            // OP_RETURN
+            vec![0x6a, 0x01, 0x00],
+            // witness v1 program
+            [vec![0x51, 0x20], vec![123; 32]].concat(),
+            // P2WPKH with a trailing byte
+            [p2wpkh_script().to_bytes(), vec![0x00]].concat(),
+            // oversized script
+            vec![0x00; 10_000],
+        ];
+        for script in nonstandard {
+            assert!(!is_standard_withdrawal_script(&script.into()));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Withdrawal script must be P2PKH, P2SH, P2WPKH or P2WSH")]
+    fn withdrawal_nonstandard_script() {
+        let mut net = MockNet::new();
+        let mut peg_state = PegState::wrap_store(&mut net.store).unwrap();
+        let mut account_state = AccountState::wrap_store(&mut net.store2).unwrap();
+
+        let sender = create_sender(&mut account_state, 1234, 0);
+
+        let mut tx = WithdrawalTransaction {
+            from: sender.address.clone(),
+            to: vec![0x6a, 0x01, 0x00].into(),
+            amount: 1000,
+            signature: vec![],
+            nonce: 0,
+        };
+        let sig = sign(&mut tx, sender.privkey);
+        tx.signature = sig;
+        withdrawal_tx(&mut peg_state, &mut account_state, tx).unwrap();
+    }
+
+    #[test]
+    fn withdrawal_to_each_standard_script() {
+        let mut net = MockNet::new();
+        let mut peg_state = PegState::wrap_store(&mut net.store).unwrap();
+        let mut account_state = AccountState::wrap_store(&mut net.store2).unwrap();
+
+        let sender = create_sender(&mut account_state, 10_000, 0);
+
+        let scripts = vec![
+            p2pkh_script(),
+            p2sh_script(),
+            p2wpkh_script(),
+            p2wsh_script(),
+        ];
+        for (nonce, script) in scripts.into_iter().enumerate() {
+            let mut tx = WithdrawalTransaction {
+                from: sender.address.clone(),
+                to: script,
+                amount: 1000,
+                signature: vec![],
+                nonce: nonce as u64,
+            };
+            let sig = sign(&mut tx, sender.privkey);
+            tx.signature = sig;
+            withdrawal_tx(&mut peg_state, &mut account_state, tx).unwrap();
+        }
+        assert_eq!(peg_state.pending_withdrawals.len(), 4);
     }
 
     #[test]
@@ -739,7 +1991,7 @@ mod tests {
 
         let mut tx = WithdrawalTransaction {
             from: sender.address.clone(),
-            to: bitcoin::Script::from(vec![123]),
+            to: p2wpkh_script(),
             amount: 1000,
             signature: vec![],
             nonce: 0,
@@ -761,7 +2013,7 @@ mod tests {
 
         let mut tx = WithdrawalTransaction {
             from: sender.address.clone(),
-            to: bitcoin::Script::from(vec![123]),
+            to: p2wpkh_script(),
             amount: 1000,
             signature: vec![],
             nonce: 0,
@@ -782,7 +2034,7 @@ mod tests {
 
         let mut tx = WithdrawalTransaction {
             from: sender.address.clone(),
-            to: bitcoin::Script::from(vec![123]),
+            to: p2wpkh_script(),
             amount: 2000,
             signature: vec![],
             nonce: 0,
@@ -804,7 +2056,7 @@ mod tests {
 
         let mut tx = WithdrawalTransaction {
             from: sender_address,
-            to: bitcoin::Script::from(vec![123]),
+            to: p2wpkh_script(),
             amount: 1000,
             signature: vec![],
             nonce: 0,
@@ -823,6 +2075,7 @@ mod tests {
 
         let tx = SignatureTransaction {
             signatures: vec![],
+            signatory_set_index: 0,
             signatory_index: 0,
         };
         signature_tx(&mut state, tx).unwrap();
@@ -836,6 +2089,7 @@ mod tests {
 
         let tx = SignatureTransaction {
             signatures: vec![],
+            signatory_set_index: 0,
             signatory_index: 0,
         };
         signature_tx(&mut state, tx).unwrap();
@@ -849,6 +2103,7 @@ mod tests {
 
         let tx = SignatureTransaction {
             signatures: vec![vec![1, 2, 3]],
+            signatory_set_index: 0,
             signatory_index: 0,
         };
         signature_tx(&mut state, tx).unwrap();
@@ -862,6 +2117,7 @@ mod tests {
 
         let tx = SignatureTransaction {
             signatures: vec![vec![123; 64]],
+            signatory_set_index: 0,
             signatory_index: 123,
         };
         signature_tx(&mut state, tx).unwrap();
@@ -875,6 +2131,7 @@ mod tests {
 
         let tx = SignatureTransaction {
             signatures: vec![vec![123; 64]],
+            signatory_set_index: 0,
             signatory_index: 0,
         };
         signature_tx(&mut state, tx).unwrap();
@@ -904,25 +2161,617 @@ mod tests {
         assert_eq!(state.finalized_checkpoint.withdrawals.len(), 0);
     }
 
-    fn signatory_sign(state: &mut PegState<&mut MapStore>, priv_key: &secp256k1::SecretKey) {
-        let btc_tx = state.active_checkpoint_tx().unwrap();
+    #[test]
+    fn checkpoint_history() {
+        let mut net = MockNet::with_active_checkpoint();
+        let mut state = PegState::wrap_store(&mut net.store).unwrap();
 
-        let signatory_set_index = state.active_checkpoint.signatory_set_index.get().unwrap();
-        let signatories = state
-            .signatory_sets
-            .get_fixed(signatory_set_index)
+        let record = state.checkpoints.get(1).unwrap().unwrap();
+        assert_eq!(record.status, CheckpointStatus::Signing);
+        assert_eq!(record.signatory_set_index, 0);
+        assert_eq!(record.inputs.len(), 1);
+        assert_eq!(record.inputs[0].value, 100_000_000);
+        assert_eq!(record.outputs.len(), 1);
+        assert_eq!(record.signatures, vec![None]);
+        assert_eq!(record.tx, None);
+
+        signatory_sign(&mut state, &net.validator_privkeys[0]);
+
+        let record = state.checkpoints.get(1).unwrap().unwrap();
+        assert_eq!(record.status, CheckpointStatus::Finalized);
+        assert!(record.signatures[0].is_some());
+        assert_eq!(
+            record.tx,
+            Some(bitcoin::consensus::encode::serialize(
+                &state.finalized_checkpoint_tx().unwrap()
+            ))
+        );
+
+        // the record outlives the finalized checkpoint fields
+        begin_block_at(&mut state, &net.validators, CHECKPOINT_INTERVAL * 5);
+        signatory_sign(&mut state, &net.validator_privkeys[0]);
+
+        assert_eq!(state.checkpoint_index.get().unwrap(), 2);
+        let first = state.checkpoints.get(1).unwrap().unwrap();
+        let second = state.checkpoints.get(2).unwrap().unwrap();
+        assert_eq!(first.status, CheckpointStatus::Finalized);
+        assert_eq!(second.status, CheckpointStatus::Finalized);
+        assert_eq!(second.inputs[0].outpoint.index, 0);
+        assert_ne!(first.tx, second.tx);
+    }
+
+    #[test]
+    fn reserve_report() {
+        let mut net = MockNet::with_active_checkpoint();
+        let mut state = PegState::wrap_store(&mut net.store).unwrap();
+        let supply = AccountState::wrap_store(&mut net.store2)
             .unwrap()
-            .signatories;
+            .supply()
+            .unwrap();
+        assert_eq!(supply, 100_000_000);
+
+        // the active checkpoint's fee is paid out of the reserve, with no
+        // withdrawals to cover it
+        let active = state.reserve_report(supply).unwrap();
+        assert_eq!(active.utxo_value, 0);
+        assert_eq!(active.active_input_value, 100_000_000);
+        assert_eq!(active.active_withdrawal_value, 0);
+        assert!(active.active_fee > 0);
+        assert_eq!(active.finalized_change, None);
+        assert_eq!(active.unbacked_fees(), active.active_fee as i128);
+        assert!(active.is_balanced());
+
+        signatory_sign(&mut state, &net.validator_privkeys[0]);
+
+        let change = 100_000_000 - active.active_fee;
+        let finalized = state.reserve_report(supply).unwrap();
+        assert_eq!(finalized.utxo_value, change);
+        assert_eq!(finalized.active_input_value, 0);
+        assert_eq!(finalized.finalized_change, Some((change, true)));
+        assert_eq!(finalized.checkpoint_fees, active.active_fee);
+        assert_eq!(finalized.unbacked_fees(), active.unbacked_fees());
+        assert!(finalized.is_balanced());
+
+        // a missing change output is flagged
+        state.utxos.pop_front().unwrap();
+        let report = state.reserve_report(supply).unwrap();
+        assert_eq!(report.finalized_change, Some((change, false)));
+        assert_eq!(report.discrepancy(), -(change as i128));
+        assert!(!report.is_balanced());
+    }
+
+    #[test]
+    fn checkpoint_signing_timeout() {
+        let mut net = MockNet::with_active_checkpoint();
+        let mut state = PegState::wrap_store(&mut net.store).unwrap();
+        let start_time = CHECKPOINT_INTERVAL * 2;
+
+        // not timed out yet
+        begin_block_at(
+            &mut state,
+            &net.validators,
+            start_time + CHECKPOINT_INTERVAL,
+        );
+        assert_eq!(state.checkpoint_index.get().unwrap(), 1);
+        assert!(state.active_checkpoint.is_active.get().unwrap());
+
+        // the validator set changes while the checkpoint is stuck
+        let (_, new_val_pubkey) = create_keypair(2);
+        let mut validators = BTreeMap::new();
+        validators.insert(new_val_pubkey.serialize().to_vec(), 100);
+
+        begin_block_at(
+            &mut state,
+            &validators,
+            start_time + CHECKPOINT_SIGNING_TIMEOUT + 1,
+        );
+
+        let aborted = state.checkpoints.get(1).unwrap().unwrap();
+        assert_eq!(aborted.status, CheckpointStatus::Aborted);
+        assert_eq!(state.signatory_sets.len(), 2);
+
+        // the UTXO is retried right away by a new checkpoint, with change paid
+        // to the recomputed signatory set
+        assert_eq!(state.checkpoint_index.get().unwrap(), 2);
+        assert!(state.active_checkpoint.is_active.get().unwrap());
+        assert_eq!(state.active_checkpoint.utxos.len(), 1);
+        assert_eq!(state.active_checkpoint.signatures.len(), 1);
+        assert_eq!(state.utxos.len(), 0);
+        assert_eq!(
+            state.active_checkpoint.signatory_set_index.get().unwrap(),
+            1
+        );
+        let retry = state.checkpoints.get(2).unwrap().unwrap();
+        assert_eq!(retry.status, CheckpointStatus::Signing);
+        assert_eq!(
+            retry.inputs[0].outpoint.txid,
+            aborted.inputs[0].outpoint.txid
+        );
+
+        // the old signatory set can still sign its inputs
+        signatory_sign(&mut state, &net.validator_privkeys[0]);
+        assert!(!state.active_checkpoint.is_active.get().unwrap());
+    }
+
+    fn mock_recovery() -> Recovery {
+        Recovery {
+            timelock: 144,
+            threshold: 1,
+            pubkeys: vec![bitcoin::PublicKey {
+                key: create_keypair(9).1,
+                compressed: true,
+            }],
+        }
+    }
+
+    #[test]
+    fn checkpoint_with_recovery_path() {
+        let snapshot = SignatorySetSnapshot {
+            time: 1,
+            signatories: signatories_from_validators(&mock_validator_set().0).unwrap(),
+            recovery: Some(mock_recovery()),
+        };
+        let deposit = build_tx(vec![build_txout(
+            100_000_000,
+            snapshot.output_script(vec![123; 33]),
+        )]);
+        let mut net = MockNet::with_btc_block(build_block(vec![deposit]));
+        let (tx, proof) = net.create_btc_proof();
+
+        let mut peg_state = PegState::wrap_store(&mut net.store).unwrap();
+        let mut account_state = AccountState::wrap_store(&mut net.store2).unwrap();
+        peg_state.recovery.set(Some(mock_recovery())).unwrap();
+        peg_state
+            .signatory_sets
+            .push_back(snapshot.clone())
+            .unwrap();
+
+        let deposit = DepositTransaction {
+            height: 0,
+            proof,
+            tx,
+            block_index: 0,
+            recipients: vec![vec![123; 33]],
+        };
+        deposit_tx(&mut peg_state, &mut account_state, deposit).unwrap();
+        assert_eq!(peg_state.utxos.get(0).unwrap().signatory_set_index, 1);
+
+        begin_checkpoint(&mut peg_state, &net.validators);
+        signatory_sign(&mut peg_state, &net.validator_privkeys[0]);
+        assert!(!peg_state.active_checkpoint.is_active.get().unwrap());
+
+        // the signatory branch is selected in the witness
+        let btc_tx = peg_state.finalized_checkpoint_tx().unwrap();
+        let witness = &btc_tx.input[0].witness;
+        assert_eq!(witness.len(), 3);
+        assert_eq!(witness[1], vec![1]);
+        assert_eq!(witness[2], snapshot.redeem_script(vec![123; 33]).to_bytes());
+
+        // the change keeps the recovery path
+        assert_eq!(
+            btc_tx.output[0].script_pubkey,
+            snapshot.output_script(vec![])
+        );
+        assert_eq!(
+            peg_state.last_refresh_time.get().unwrap(),
+            CHECKPOINT_INTERVAL * 2
+        );
+    }
+
+    #[test]
+    fn recovery_refresh_ignores_minimum_value() {
+        let mut net = MockNet::new();
+        let mut state = PegState::wrap_store(&mut net.store).unwrap();
+        state
+            .signatory_sets
+            .push_back(SignatorySetSnapshot {
+                time: 1,
+                signatories: signatories_from_validators(&net.validators).unwrap(),
+                recovery: Some(mock_recovery()),
+            })
+            .unwrap();
+        state.utxos.push_back(mock_utxo(0, 5_000, 1)).unwrap();
+
+        // too small to checkpoint before the refresh is due
+        begin_checkpoint(&mut state, &net.validators);
+        assert!(!state.active_checkpoint.is_active.get_or_default().unwrap());
 
-        let signatures =
+        begin_block_at(
+            &mut state,
+            &net.validators,
+            mock_recovery().refresh_interval() + 1,
+        );
+        assert!(state.active_checkpoint.is_active.get().unwrap());
+        assert_eq!(state.active_checkpoint.utxos.len(), 1);
+    }
+
+    fn mock_utxo(i: u32, value: u64, signatory_set_index: u64) -> Utxo {
+        Utxo {
+            outpoint: crate::core::bitcoin::Outpoint {
+                txid: [123; 32],
+                index: i,
+            },
+            value,
+            signatory_set_index,
+            data: vec![123; 33],
+        }
+    }
+
+    /// Runs the peg's block logic for a Tendermint block at `time`.
+    fn begin_block_at(
+        state: &mut PegState<&mut MapStore>,
+        validators: &BTreeMap<Vec<u8>, u64>,
+        time: u64,
+    ) {
+        let mut header: TendermintHeader = Default::default();
+        let mut timestamp = Timestamp::new();
+        timestamp.set_seconds(time as i64);
+        header.set_time(timestamp);
+        super::begin_block(state, validators, header).unwrap();
+    }
+
+    fn begin_checkpoint(state: &mut PegState<&mut MapStore>, validators: &BTreeMap<Vec<u8>, u64>) {
+        begin_block_at(state, validators, CHECKPOINT_INTERVAL * 2);
+    }
+
+    #[test]
+    fn checkpoint_input_limit_carries_over() {
+        let mut net = MockNet::new();
+        let mut state = PegState::wrap_store(&mut net.store).unwrap();
+        for i in 0..MAX_CHECKPOINT_INPUTS as u32 + 5 {
+            state.utxos.push_back(mock_utxo(i, 100_000, 0)).unwrap();
+        }
+
+        begin_checkpoint(&mut state, &net.validators);
+
+        assert!(state.active_checkpoint.is_active.get().unwrap());
+        assert_eq!(
+            state.active_checkpoint.utxos.len(),
+            MAX_CHECKPOINT_INPUTS as u64
+        );
+        assert_eq!(state.utxos.len(), 5);
+        assert_eq!(
+            state.utxos.get(0).unwrap().outpoint.index,
+            MAX_CHECKPOINT_INPUTS as u32
+        );
+    }
+
+    #[test]
+    fn checkpoint_withdrawals_limited_by_inputs() {
+        let mut net = MockNet::new();
+        let mut state = PegState::wrap_store(&mut net.store).unwrap();
+        state.utxos.push_back(mock_utxo(0, 100_000, 0)).unwrap();
+        for _ in 0..2 {
             state
-                .active_utxos()
-                .unwrap()
+                .pending_withdrawals
+                .push_back(Withdrawal {
+                    value: 60_000,
+                    script: p2wpkh_script().into(),
+                })
+                .unwrap();
+        }
+
+        begin_checkpoint(&mut state, &net.validators);
+
+        assert!(state.active_checkpoint.is_active.get().unwrap());
+        assert_eq!(state.active_checkpoint.withdrawals.len(), 1);
+        assert_eq!(state.pending_withdrawals.len(), 1);
+        state.active_checkpoint_tx().unwrap();
+    }
+
+    #[test]
+    fn checkpoint_weight_limit() {
+        let mut net = MockNet::new();
+        let mut state = PegState::wrap_store(&mut net.store).unwrap();
+
+        let mut signatories = SignatorySet::new();
+        for i in 1..=40 {
+            signatories.set(Signatory::new(
+                bitcoin::PublicKey {
+                    key: create_keypair(i).1,
+                    compressed: true,
+                },
+                100,
+            ));
+        }
+        state
+            .signatory_sets
+            .push_back(SignatorySetSnapshot {
+                time: 1,
+                signatories,
+                recovery: None,
+            })
+            .unwrap();
+        for i in 0..MAX_CHECKPOINT_INPUTS as u32 {
+            state.utxos.push_back(mock_utxo(i, 100_000, 1)).unwrap();
+        }
+
+        let (mut utxos, withdrawals) = state.checkpoint_selection(1).unwrap();
+        assert!(utxos.len() < MAX_CHECKPOINT_INPUTS);
+        assert!(
+            state.checkpoint_fee(&utxos, &withdrawals, 1).unwrap() <= MAX_CHECKPOINT_WEIGHT / 4
+        );
+
+        utxos.push(mock_utxo(0, 100_000, 1));
+        assert!(state.checkpoint_fee(&utxos, &withdrawals, 1).unwrap() > MAX_CHECKPOINT_WEIGHT / 4);
+    }
+
+    #[test]
+    fn min_checkpoint_weight_param() {
+        use super::super::{min_checkpoint_weight, Params};
+        use crate::core::signatory_set::MAX_RECOVERY_KEYS;
+
+        let params = Params {
+            max_checkpoint_weight: min_checkpoint_weight() - 1,
+            ..Default::default()
+        };
+        assert!(params.validate().is_err());
+
+        let mut net = MockNet::new();
+        let mut state = PegState::wrap_store(&mut net.store).unwrap();
+        let params = Params {
+            max_checkpoint_weight: min_checkpoint_weight(),
+            ..Default::default()
+        };
+        params.validate().unwrap();
+        state.params.set(params).unwrap();
+
+        // the largest signatory set with the largest recovery path
+        let mut signatories = SignatorySet::new();
+        for i in 1..=MAX_SIGNATORIES as u8 {
+            signatories.set(Signatory::new(
+                bitcoin::PublicKey {
+                    key: create_keypair(i).1,
+                    compressed: true,
+                },
+                1 << 20,
+            ));
+        }
+        let recovery = Recovery {
+            timelock: u16::MAX,
+            threshold: MAX_RECOVERY_KEYS as u8,
+            pubkeys: (0..MAX_RECOVERY_KEYS as u8)
+                .map(|i| bitcoin::PublicKey {
+                    key: create_keypair(100 + i).1,
+                    compressed: true,
+                })
+                .collect(),
+        };
+        state
+            .signatory_sets
+            .push_back(SignatorySetSnapshot {
+                time: 1,
+                signatories,
+                recovery: Some(recovery),
+            })
+            .unwrap();
+        for i in 0..2 {
+            state.utxos.push_back(mock_utxo(i, 100_000, 1)).unwrap();
+        }
+
+        let (utxos, _) = state.checkpoint_selection(1).unwrap();
+        assert_eq!(utxos.len(), 1);
+    }
+
+    #[test]
+    fn checkpoint_fee_covers_signed_weight() {
+        use super::super::DEFAULT_CHECKPOINT_FEE_RATE;
+
+        let mut net = MockNet::with_active_checkpoint();
+        let mut state = PegState::wrap_store(&mut net.store).unwrap();
+        assert_eq!(
+            state.active_checkpoint.fee_rate.get().unwrap(),
+            DEFAULT_CHECKPOINT_FEE_RATE
+        );
+
+        signatory_sign(&mut state, &net.validator_privkeys[0]);
+
+        let btc_tx = state.finalized_checkpoint_tx().unwrap();
+        let full_size = bitcoin::consensus::encode::serialize(&btc_tx).len() as u64;
+        let mut stripped_tx = btc_tx.clone();
+        for input in stripped_tx.input.iter_mut() {
+            input.witness = vec![];
+        }
+        let base_size = bitcoin::consensus::encode::serialize(&stripped_tx).len() as u64;
+        let vsize = (base_size * 3 + full_size + 3) / 4;
+
+        let fee = 100_000_000 - btc_tx.output[0].value;
+        assert!(fee >= vsize * DEFAULT_CHECKPOINT_FEE_RATE);
+        // estimate only overshoots by the slack in signature length
+        assert!(fee <= (vsize + 2) * DEFAULT_CHECKPOINT_FEE_RATE);
+    }
+
+    #[test]
+    #[should_panic(expected = "Checkpoint does not spend from signatory set")]
+    fn signatory_invalid_signatory_set() {
+        let mut net = MockNet::with_active_checkpoint();
+        let mut state = PegState::wrap_store(&mut net.store).unwrap();
+
+        let tx = SignatureTransaction {
+            signatures: vec![vec![123; 64]],
+            signatory_set_index: 1,
+            signatory_index: 0,
+        };
+        signature_tx(&mut state, tx).unwrap();
+    }
+
+    /// Deposits to both the oldest signatory set (key 1) and a newer one
+    /// (key 2), then starts a checkpoint. Returns the newer set and its
+    /// signatory's private key.
+    fn deposit_to_two_signatory_sets() -> (MockNet, secp256k1::SecretKey, SignatorySet) {
+        let (new_val_privkey, new_val_pubkey) = create_keypair(2);
+        let mut new_signatories = SignatorySet::new();
+        new_signatories.set(Signatory::new(
+            bitcoin::PublicKey {
+                key: new_val_pubkey,
+                compressed: true,
+            },
+            100,
+        ));
+
+        let old_deposit = mock_deposit();
+        let new_deposit = build_tx(vec![build_txout(
+            50_000_000,
+            crate::core::signatory_set::output_script(&new_signatories, vec![124; 33]),
+        )]);
+        let block = build_block(vec![old_deposit, new_deposit]);
+        let mut net = MockNet::with_btc_block(block);
+        let proofs = vec![net.create_btc_proof_at(0), net.create_btc_proof_at(1)];
+
+        {
+            let mut peg_state = PegState::wrap_store(&mut net.store).unwrap();
+            let mut account_state = AccountState::wrap_store(&mut net.store2).unwrap();
+
+            peg_state
+                .signatory_sets
+                .push_back(SignatorySetSnapshot {
+                    time: 1,
+                    signatories: new_signatories.clone(),
+                    recovery: None,
+                })
+                .unwrap();
+
+            let recipients = vec![vec![123; 33], vec![124; 33]];
+            for (i, ((tx, proof), recipient)) in proofs.into_iter().zip(recipients).enumerate() {
+                let deposit = DepositTransaction {
+                    height: 0,
+                    proof,
+                    tx,
+                    block_index: i as u32,
+                    recipients: vec![recipient],
+                };
+                deposit_tx(&mut peg_state, &mut account_state, deposit).unwrap();
+            }
+
+            begin_block_at(&mut peg_state, &net.validators, CHECKPOINT_INTERVAL * 2);
+        }
+
+        (net, new_val_privkey, new_signatories)
+    }
+
+    #[test]
+    fn checkpoint_spends_multiple_signatory_sets() {
+        let (mut net, new_val_privkey, new_signatories) = deposit_to_two_signatory_sets();
+
+        let mut state = PegState::wrap_store(&mut net.store).unwrap();
+        assert_eq!(
+            input_signatory_set_indexes(&state.active_utxos().unwrap()),
+            vec![0, 1]
+        );
+        assert_eq!(state.active_checkpoint.signatures.len(), 2);
+
+        // the old set signing alone is not enough
+        signatory_sign(&mut state, &net.validator_privkeys[0]);
+        assert!(state.active_checkpoint.is_active.get().unwrap());
+
+        signatory_sign(&mut state, &new_val_privkey);
+        assert!(!state.active_checkpoint.is_active.get().unwrap());
+
+        let btc_tx = state.finalized_checkpoint_tx().unwrap();
+        assert_eq!(btc_tx.input.len(), 2);
+        let old_script = crate::core::signatory_set::redeem_script(
+            &signatories_from_validators(&mock_validator_set().0).unwrap(),
+            vec![123; 33],
+        );
+        let new_script = crate::core::signatory_set::redeem_script(&new_signatories, vec![124; 33]);
+        assert_eq!(btc_tx.input[0].witness.len(), 2);
+        assert_eq!(btc_tx.input[0].witness[1], old_script.to_bytes());
+        assert_eq!(btc_tx.input[1].witness.len(), 2);
+        assert_eq!(btc_tx.input[1].witness[1], new_script.to_bytes());
+
+        // change is paid to the newest signatory set
+        assert_eq!(state.utxos.len(), 1);
+        assert_eq!(state.utxos.get(0).unwrap().signatory_set_index, 1);
+    }
+
+    #[test]
+    fn offline_signatory_set() {
+        let (mut net, new_val_privkey, _) = deposit_to_two_signatory_sets();
+        let mut state = PegState::wrap_store(&mut net.store).unwrap();
+        let start_time = CHECKPOINT_INTERVAL * 2;
+        let old_utxo = state.active_utxos().unwrap()[0].clone();
+        assert_eq!(old_utxo.signatory_set_index, 0);
+
+        // the newer set signs its inputs, but the oldest set never signs, so
+        // the checkpoint times out
+        signatory_sign(&mut state, &new_val_privkey);
+        assert!(state.active_checkpoint.is_active.get().unwrap());
+        let mut validators = BTreeMap::new();
+        validators.insert(create_keypair(2).1.serialize().to_vec(), 100);
+        begin_block_at(
+            &mut state,
+            &validators,
+            start_time + CHECKPOINT_SIGNING_TIMEOUT + 1,
+        );
+        assert_eq!(
+            state.checkpoints.get(1).unwrap().unwrap().status,
+            CheckpointStatus::Aborted
+        );
+
+        // only the set holding up the checkpoint is backed off
+        assert!(state.is_backed_off(0).unwrap());
+        assert!(!state.is_backed_off(1).unwrap());
+
+        // the retry spends the newer set's deposit instead of the same input
+        assert_eq!(state.checkpoint_index.get().unwrap(), 2);
+        assert_eq!(
+            input_signatory_set_indexes(&state.active_utxos().unwrap()),
+            vec![1]
+        );
+        assert_eq!(
+            state.utxos.get(0).unwrap().outpoint.txid,
+            old_utxo.outpoint.txid
+        );
+
+        signatory_sign(&mut state, &new_val_privkey);
+        assert!(!state.active_checkpoint.is_active.get().unwrap());
+        assert_eq!(
+            state.checkpoints.get(2).unwrap().unwrap().status,
+            CheckpointStatus::Finalized
+        );
+
+        // the oldest set's output stays queued ahead of the change
+        let newest = state
+            .signatory_sets
+            .fixed_index(state.signatory_sets.len() - 1);
+        let pending: Vec<_> = state
+            .utxos
+            .iter()
+            .map(|utxo| utxo.unwrap().signatory_set_index)
+            .collect();
+        assert_eq!(pending, vec![0, newest]);
+
+        // the oldest set's output is retried once the backoff ends
+        let until = state.signing_backoffs.get(0).unwrap().unwrap().until;
+        begin_block_at(&mut state, &validators, until + 1);
+        assert_eq!(
+            state.active_utxos().unwrap()[0].outpoint.txid,
+            old_utxo.outpoint.txid
+        );
+    }
+
+    fn signatory_sign(state: &mut PegState<&mut MapStore>, priv_key: &secp256k1::SecretKey) {
+        let btc_tx = state.active_checkpoint_tx().unwrap();
+        let pubkey = secp256k1::PublicKey::from_secret_key(&SECP, priv_key);
+        let utxos = state.active_utxos().unwrap();
+
+        for signatory_set_index in input_signatory_set_indexes(&utxos) {
+            let snapshot = state.signatory_sets.get_fixed(signatory_set_index).unwrap();
+            let signatory_index = match snapshot
+                .signatories
+                .iter()
+                .position(|signatory| signatory.pubkey.key == pubkey)
+            {
+                Some(index) => index,
+                None => continue,
+            };
+
+            let signatures = utxos
                 .iter()
                 .enumerate()
+                .filter(|(_, utxo)| utxo.signatory_set_index == signatory_set_index)
                 .map(|(i, utxo)| {
-                    let script =
-                        crate::core::signatory_set::redeem_script(&signatories, utxo.data.clone());
+                    let script = snapshot.redeem_script(utxo.data.clone());
                     let sighash = bitcoin::util::bip143::SighashComponents::new(&btc_tx)
                         .sighash_all(&btc_tx.input[i], &script, utxo.value);
                     let message = secp256k1::Message::from_slice(&sighash[..]).unwrap();
@@ -931,11 +2780,13 @@ mod tests {
                 })
                 .collect();
 
-        let tx = crate::core::primitives::transaction::SignatureTransaction {
-            signatures,
-            signatory_index: 0,
-        };
+            let tx = crate::core::primitives::transaction::SignatureTransaction {
+                signatures,
+                signatory_set_index,
+                signatory_index: signatory_index as u16,
+            };
 
-        signature_tx(state, tx).unwrap();
+            signature_tx(state, tx).unwrap();
+        }
     }
 }
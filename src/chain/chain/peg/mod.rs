@@ -1,9 +1,40 @@
 pub mod handlers;
+mod params;
 mod state;
 
+pub use params::*;
 pub use state::*;
 
 pub const SIGNATORY_CHANGE_INTERVAL: u64 = 8;
 pub const CHECKPOINT_INTERVAL: u64 = 60 * 60 * 3;
-pub const CHECKPOINT_FEE_AMOUNT: u64 = 1_000;
+/// Time after which a checkpoint which has not collected enough signatures is
+/// aborted and its inputs and withdrawals are retried.
+pub const CHECKPOINT_SIGNING_TIMEOUT: u64 = 60 * 60 * 6;
+/// Whether an aborted checkpoint also causes the signatory set to be
+/// recomputed from the current validators.
+pub const CHECKPOINT_TIMEOUT_RESETS_SIGNATORIES: bool = true;
+/// Default fee rate, in satoshis per virtual byte, paid by checkpoint
+/// transactions. Configured by the `checkpoint_fee_rate` parameter.
+pub const DEFAULT_CHECKPOINT_FEE_RATE: u64 = 10;
 pub const CHECKPOINT_MINIMUM_VALUE: u64 = 10_000;
+/// Default number of most recent signatory sets whose deposit scripts are
+/// still accepted, so deposits sent to an address shortly before a rotation
+/// are not lost. Configured by the `deposit_signatory_set_window` parameter.
+pub const DEPOSIT_SIGNATORY_SET_WINDOW: u64 = 2;
+/// Number of blocks the SPV tip must be above a deposit's block before the
+/// deposit is credited. Deposits proven earlier are held as pending.
+pub const DEPOSIT_CONFIRMATION_DEPTH: u32 = 6;
+/// Number of blocks a credited deposit's block is watched for reorgs before it
+/// is considered final.
+pub const DEPOSIT_REORG_TRACKING_DEPTH: u32 = 100;
+/// Maximum number of inputs spent by a single checkpoint transaction.
+pub const MAX_CHECKPOINT_INPUTS: usize = 100;
+/// Maximum number of outputs, including the change output, of a single
+/// checkpoint transaction.
+pub const MAX_CHECKPOINT_OUTPUTS: usize = 250;
+/// Maximum estimated weight of a signed checkpoint transaction, Bitcoin Core's
+/// standardness limit of 100,000 virtual bytes.
+pub const MAX_CHECKPOINT_WEIGHT: u64 = 400_000;
+/// Percentage of voting power which must be exceeded by validators signaling
+/// to pause or resume the peg. A third is enough to halt the chain anyway.
+pub const PAUSE_THRESHOLD: u64 = 33;
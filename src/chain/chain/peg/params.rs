@@ -0,0 +1,117 @@
+use super::min_checkpoint_weight;
+use super::{
+    CHECKPOINT_INTERVAL, CHECKPOINT_MINIMUM_VALUE, CHECKPOINT_SIGNING_TIMEOUT,
+    CHECKPOINT_TIMEOUT_RESETS_SIGNATORIES, DEFAULT_CHECKPOINT_FEE_RATE, DEPOSIT_CONFIRMATION_DEPTH,
+    DEPOSIT_REORG_TRACKING_DEPTH, DEPOSIT_SIGNATORY_SET_WINDOW, MAX_CHECKPOINT_INPUTS,
+    MAX_CHECKPOINT_OUTPUTS, MAX_CHECKPOINT_WEIGHT, PAUSE_THRESHOLD, SIGNATORY_CHANGE_INTERVAL,
+};
+use crate::core::primitives::Result;
+use failure::bail;
+use orga::{Decode, Encode, Terminated};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+
+/// Peg parameters, set at genesis and changed by governance proposals. Each
+/// defaults to the constant of the same name in the `peg` module.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Params {
+    pub signatory_change_interval: u64,
+    pub checkpoint_interval: u64,
+    pub checkpoint_signing_timeout: u64,
+    pub checkpoint_timeout_resets_signatories: bool,
+    /// Satoshis per virtual byte paid by checkpoint transactions and charged
+    /// to withdrawals. Defaults to `DEFAULT_CHECKPOINT_FEE_RATE`.
+    pub checkpoint_fee_rate: u64,
+    pub checkpoint_minimum_value: u64,
+    pub deposit_signatory_set_window: u64,
+    pub deposit_confirmation_depth: u32,
+    pub deposit_reorg_tracking_depth: u32,
+    pub max_checkpoint_inputs: usize,
+    pub max_checkpoint_outputs: usize,
+    pub max_checkpoint_weight: u64,
+    pub pause_threshold: u64,
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Params {
+            signatory_change_interval: SIGNATORY_CHANGE_INTERVAL,
+            checkpoint_interval: CHECKPOINT_INTERVAL,
+            checkpoint_signing_timeout: CHECKPOINT_SIGNING_TIMEOUT,
+            checkpoint_timeout_resets_signatories: CHECKPOINT_TIMEOUT_RESETS_SIGNATORIES,
+            checkpoint_fee_rate: DEFAULT_CHECKPOINT_FEE_RATE,
+            checkpoint_minimum_value: CHECKPOINT_MINIMUM_VALUE,
+            deposit_signatory_set_window: DEPOSIT_SIGNATORY_SET_WINDOW,
+            deposit_confirmation_depth: DEPOSIT_CONFIRMATION_DEPTH,
+            deposit_reorg_tracking_depth: DEPOSIT_REORG_TRACKING_DEPTH,
+            max_checkpoint_inputs: MAX_CHECKPOINT_INPUTS,
+            max_checkpoint_outputs: MAX_CHECKPOINT_OUTPUTS,
+            max_checkpoint_weight: MAX_CHECKPOINT_WEIGHT,
+            pause_threshold: PAUSE_THRESHOLD,
+        }
+    }
+}
+
+impl Params {
+    /// Rejects values the peg can't operate with.
+    pub fn validate(&self) -> Result<()> {
+        if self.signatory_change_interval == 0 {
+            bail!("Signatory change interval must be at least 1");
+        }
+        if self.checkpoint_fee_rate == 0 {
+            bail!("Checkpoint fee rate must be at least 1");
+        }
+        if self.deposit_signatory_set_window == 0 {
+            bail!("Deposit signatory set window must be at least 1");
+        }
+        if self.max_checkpoint_inputs == 0 {
+            bail!("Checkpoints must have at least 1 input");
+        }
+        if self.max_checkpoint_weight < min_checkpoint_weight() {
+            bail!(
+                "Checkpoint weight must be at least {} to spend any reserve output",
+                min_checkpoint_weight()
+            );
+        }
+        // one output is always the change
+        if self.max_checkpoint_outputs < 2 {
+            bail!("Checkpoints must have at least 2 outputs");
+        }
+        if self.pause_threshold >= 100 {
+            bail!("Pause threshold must be below 100 percent");
+        }
+        Ok(())
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        bincode::deserialize(bytes).map_err(|err| failure::format_err!("{}", err))
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        bincode::serialize(self).map_err(|err| failure::format_err!("{}", err))
+    }
+}
+
+impl Encode for Params {
+    fn encode_into<W: Write>(&self, dest: &mut W) -> Result<()> {
+        let bytes = Params::encode(self)?;
+        dest.write_all(bytes.as_slice())?;
+        Ok(())
+    }
+
+    fn encoding_length(&self) -> Result<usize> {
+        let bytes = Params::encode(self)?;
+        Ok(bytes.len())
+    }
+}
+
+impl Decode for Params {
+    fn decode<R: Read>(mut input: R) -> Result<Self> {
+        let mut buf = vec![];
+        input.read_to_end(&mut buf)?;
+        Params::decode(buf.as_slice())
+    }
+}
+
+impl !Terminated for Params {}
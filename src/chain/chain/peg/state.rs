@@ -1,12 +1,18 @@
-use super::CHECKPOINT_FEE_AMOUNT;
-use crate::core::bitcoin::bitcoin;
+use super::Params;
+use crate::core::bitcoin::{bitcoin, Network};
 use crate::core::primitives::{Result, Signature, Withdrawal};
-use crate::core::signatory_set::SignatorySetSnapshot;
+use crate::core::signatory_set::{
+    Recovery, SignatorySetSnapshot, MAX_RECOVERY_KEYS, MAX_SIGNATORIES,
+};
+use bitcoin::hashes::Hash;
 use failure::bail;
 use orga::{
-    collections::{Deque, Set},
-    state, Decode, Encode, Store, Value, Wrapper,
+    collections::{Deque, Map, Set},
+    state, Decode, Encode, Store, Terminated, Value, Wrapper,
 };
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{Read, Write};
 
 #[state]
 pub struct State {
@@ -20,9 +26,48 @@ pub struct State {
     pub checkpoint_index: Value<u64>,
     pub headers: Wrapper,
     pub finalized_checkpoint_txs: Deque<Vec<u8>>,
+    pub pending_deposits: Deque<Deposit>,
+    pub recent_deposits: Deque<Deposit>,
+    pub reorged_deposits: Deque<ReorgedDeposit>,
+    pub checkpoints: Map<u64, Checkpoint>,
+    pub recovery: Value<Option<Recovery>>,
+    pub last_refresh_time: Value<u64>,
+    pub network: Value<Network>,
+    /// Time of the current Tendermint block, which Bitcoin headers may not be
+    /// too far ahead of.
+    pub block_time: Value<u64>,
+    pub params: Value<Params>,
+    /// Set by governance or by the validators' pause signals to stop deposits,
+    /// withdrawals and new checkpoints.
+    pub paused: Value<bool>,
+    /// Incremented each time the paused flag changes, so pause signals only
+    /// count towards the change they were made for.
+    pub pause_round: Value<u64>,
+    /// The round in which each validator last signaled, by public key.
+    pub pause_signals: Map<[u8; 33], u64>,
+    /// Total fees withdrawers have paid towards checkpoint transactions. They
+    /// are burned from the supply but stay in the reserve until a checkpoint
+    /// pays them to miners.
+    pub withdrawal_fees: Value<u64>,
+    /// Total miner fees paid out of the reserve by finalized checkpoints.
+    pub checkpoint_fees: Value<u64>,
+    /// Signatory sets which held up a checkpoint the other sets had signed, by
+    /// fixed index. Their outputs are left out of checkpoints until the
+    /// backoff ends.
+    pub signing_backoffs: Map<u64, SigningBackoff>,
+}
+
+/// How long a signatory set's outputs are left out of checkpoints after its
+/// signatories failed to sign one in time.
+#[derive(Clone, Debug, Default, Encode, Decode, PartialEq)]
+pub struct SigningBackoff {
+    /// Number of checkpoints in a row the set failed to sign.
+    pub failures: u32,
+    /// Block time until which the set's outputs are not spent.
+    pub until: u64,
 }
 
-#[derive(Clone, Debug, Encode, Decode)]
+#[derive(Clone, Debug, Encode, Decode, Serialize, Deserialize)]
 pub struct Utxo {
     pub outpoint: crate::core::bitcoin::Outpoint,
     pub value: u64,
@@ -30,6 +75,203 @@ pub struct Utxo {
     pub data: Vec<u8>,
 }
 
+/// A deposit output along with the height and hash of the Bitcoin block it was
+/// proven against. Deposits are held in `pending_deposits` until they are
+/// confirmed, then in `recent_deposits` while they could still be reorged out.
+/// Credited deposits whose block was removed by a reorg are moved to
+/// `reorged_deposits`.
+#[derive(Clone, Debug, Encode, Decode, Serialize, Deserialize)]
+pub struct Deposit {
+    pub height: u32,
+    pub block_hash: [u8; 32],
+    pub utxo: Utxo,
+}
+
+/// What happened to the reserve output of a credited deposit whose block was
+/// reorged out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReorgedUtxoStatus {
+    /// The output was removed from the pending reserve outputs.
+    Removed,
+    /// The output was an input of the active checkpoint, which was aborted
+    /// before the output was removed.
+    CheckpointAborted,
+    /// The output had already been spent by a finalized checkpoint, whose
+    /// transaction can no longer confirm. Operators must step in.
+    SpentByCheckpoint,
+}
+
+/// A record of a credited deposit whose block was removed by a reorg, kept in
+/// `State::reorged_deposits` for operators to review. The coins minted for it
+/// stay in circulation, so the reserve report shows them as a discrepancy.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReorgedDeposit {
+    pub deposit: Deposit,
+    /// Block time at which the reorg was processed.
+    pub time: u64,
+    pub status: ReorgedUtxoStatus,
+}
+
+impl ReorgedDeposit {
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        bincode::deserialize(bytes).map_err(|err| failure::format_err!("{}", err))
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        bincode::serialize(self).map_err(|err| failure::format_err!("{}", err))
+    }
+}
+
+impl Encode for ReorgedDeposit {
+    fn encode_into<W: Write>(&self, dest: &mut W) -> Result<()> {
+        let bytes = ReorgedDeposit::encode(self)?;
+        dest.write_all(bytes.as_slice())?;
+        Ok(())
+    }
+
+    fn encoding_length(&self) -> Result<usize> {
+        let bytes = ReorgedDeposit::encode(self)?;
+        Ok(bytes.len())
+    }
+}
+
+impl Decode for ReorgedDeposit {
+    fn decode<R: Read>(mut input: R) -> Result<Self> {
+        let mut buf = vec![];
+        input.read_to_end(&mut buf)?;
+        ReorgedDeposit::decode(buf.as_slice())
+    }
+}
+
+impl !Terminated for ReorgedDeposit {}
+
+/// The signing status of a checkpoint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CheckpointStatus {
+    /// Signatories are still submitting signatures.
+    Signing,
+    /// Enough signatures have been collected for the transaction to be
+    /// broadcast.
+    Finalized,
+    /// Not enough signatures were collected in time. The inputs and
+    /// withdrawals were returned to the pending queues.
+    Aborted,
+}
+
+/// A permanent record of a checkpoint, kept in `State::checkpoints` by
+/// checkpoint index so past checkpoints can be audited.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub status: CheckpointStatus,
+    pub signatory_set_index: u64,
+    pub fee_rate: u64,
+    /// The reserve outputs spent by the checkpoint.
+    pub inputs: Vec<Utxo>,
+    /// The withdrawal outputs followed by the change output, as value and
+    /// script pairs.
+    pub outputs: Vec<(u64, Vec<u8>)>,
+    /// The signatures submitted so far, laid out like the active checkpoint's
+    /// signature slots.
+    pub signatures: Vec<Option<Vec<Vec<u8>>>>,
+    /// The signed transaction, once the checkpoint is finalized.
+    pub tx: Option<Vec<u8>>,
+}
+
+impl Checkpoint {
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        bincode::deserialize(bytes).map_err(|err| failure::format_err!("{}", err))
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        bincode::serialize(self).map_err(|err| failure::format_err!("{}", err))
+    }
+}
+
+impl Encode for Checkpoint {
+    fn encode_into<W: Write>(&self, dest: &mut W) -> Result<()> {
+        let bytes = Checkpoint::encode(self)?;
+        dest.write_all(bytes.as_slice())?;
+        Ok(())
+    }
+
+    fn encoding_length(&self) -> Result<usize> {
+        let bytes = Checkpoint::encode(self)?;
+        Ok(bytes.len())
+    }
+}
+
+impl Decode for Checkpoint {
+    fn decode<R: Read>(mut input: R) -> Result<Self> {
+        let mut buf = vec![];
+        input.read_to_end(&mut buf)?;
+        Checkpoint::decode(buf.as_slice())
+    }
+}
+
+impl !Terminated for Checkpoint {}
+
+/// A proof-of-reserves comparison of the NBTC supply with the reserve outputs
+/// backing it, from `State::reserve_report`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReserveReport {
+    /// Total NBTC in all accounts.
+    pub supply: u64,
+    /// Value of the reserve outputs waiting to be spent by a checkpoint.
+    pub utxo_value: u64,
+    /// Value of the reserve outputs spent by the active checkpoint.
+    pub active_input_value: u64,
+    /// Value paid to withdrawals by the active checkpoint.
+    pub active_withdrawal_value: u64,
+    /// Miner fee paid by the active checkpoint.
+    pub active_fee: u64,
+    /// Value of the withdrawals not yet included in a checkpoint.
+    pub pending_withdrawal_value: u64,
+    /// The value of the last finalized checkpoint's change output, and whether
+    /// that output is still in the reserve.
+    pub finalized_change: Option<(u64, bool)>,
+    /// Total fees withdrawers have paid towards checkpoint transactions.
+    pub withdrawal_fees: u64,
+    /// Total miner fees paid by finalized checkpoints.
+    pub checkpoint_fees: u64,
+}
+
+impl ReserveReport {
+    /// The reserve value left once the active checkpoint and the pending
+    /// withdrawals have been paid out, which should equal the supply once the
+    /// miner fees are accounted for.
+    pub fn backing(&self) -> i128 {
+        self.utxo_value as i128 + self.active_input_value as i128
+            - self.active_withdrawal_value as i128
+            - self.active_fee as i128
+            - self.pending_withdrawal_value as i128
+            + self.unbacked_fees()
+    }
+
+    /// The miner fees paid out of the reserve, including the active
+    /// checkpoint's, less the withdrawal fees burned from the supply to cover
+    /// them. Checkpoints without withdrawals are paid for by the reserve, so
+    /// this is usually positive.
+    pub fn unbacked_fees(&self) -> i128 {
+        self.checkpoint_fees as i128 + self.active_fee as i128 - self.withdrawal_fees as i128
+    }
+
+    /// The backing minus the supply. A negative discrepancy means there is
+    /// more NBTC than reserve BTC to redeem it.
+    pub fn discrepancy(&self) -> i128 {
+        self.backing() - self.supply as i128
+    }
+
+    /// Returns true if the backing equals the supply and the last finalized
+    /// checkpoint's change output is in the reserve.
+    pub fn is_balanced(&self) -> bool {
+        let change_tracked = match self.finalized_change {
+            Some((_, tracked)) => tracked,
+            None => true,
+        };
+        self.discrepancy() == 0 && change_tracked
+    }
+}
+
 #[state]
 pub struct FinalizedCheckpoint {
     pub withdrawals: Deque<Withdrawal>,
@@ -37,6 +279,7 @@ pub struct FinalizedCheckpoint {
     pub utxos: Deque<Utxo>,
     pub signatures: Deque<Option<Vec<Signature>>>,
     pub next_signatory_set: Value<Option<SignatorySetSnapshot>>,
+    pub fee_rate: Value<u64>,
 }
 
 #[state]
@@ -48,105 +291,214 @@ pub struct ActiveCheckpoint {
     pub utxos: Deque<Utxo>,
     pub withdrawals: Deque<Withdrawal>,
     pub next_signatory_set: Value<Option<SignatorySetSnapshot>>,
+    pub fee_rate: Value<u64>,
+    pub start_time: Value<u64>,
 }
 
 impl<S: Store> State<S> {
+    /// Returns the Bitcoin network the peg was created for.
+    pub fn network(&self) -> Result<Network> {
+        self.network.get_or_default()
+    }
+
+    /// Returns the peg parameters set at genesis.
+    pub fn params(&self) -> Result<Params> {
+        self.params.get_or_default()
+    }
+
+    /// Returns true if the peg has been paused.
+    pub fn is_paused(&self) -> Result<bool> {
+        self.paused.get_or_default()
+    }
+
+    /// Pauses or resumes the peg, discarding the pause signals made so far.
+    pub fn set_paused(&mut self, paused: bool) -> Result<()> {
+        let round = self.pause_round.get_or_default()?;
+        self.pause_round.set(round + 1)?;
+        self.paused.set(paused)
+    }
+
     pub fn current_signatory_set(&self) -> Result<SignatorySetSnapshot> {
         Ok(self.signatory_sets.back()?.unwrap())
     }
 
-    pub fn pending_utxos(&self) -> Result<Vec<Utxo>> {
-        // TODO: don't prune utxos, support spending from older signatory set
-        let current_signatory_set_index = self
-            .signatory_sets
-            .fixed_index(self.signatory_sets.len() - 1);
-
-        self.utxos
-            .iter()
-            .filter(|utxo| match utxo {
-                Err(_) => true,
-                Ok(utxo) => utxo.signatory_set_index == current_signatory_set_index,
+    /// Returns up to `count` of the most recent signatory sets along with their
+    /// fixed indexes, newest first.
+    pub fn recent_signatory_sets(&self, count: u64) -> Result<Vec<(u64, SignatorySetSnapshot)>> {
+        let len = self.signatory_sets.len();
+        (len.saturating_sub(count)..len)
+            .rev()
+            .map(|i| {
+                Ok((
+                    self.signatory_sets.fixed_index(i),
+                    self.signatory_sets.get(i)?,
+                ))
             })
             .collect()
     }
 
+    /// Returns the fee rate, in satoshis per virtual byte, used for new
+    /// checkpoints. It is the `checkpoint_fee_rate` parameter, which
+    /// validators can change with a `SetPegParams` proposal.
+    pub fn fee_rate(&self) -> Result<u64> {
+        Ok(self.params()?.checkpoint_fee_rate)
+    }
+
+    /// Returns how often the pending reserve outputs must be moved to keep
+    /// their recovery paths from unlocking, or `None` if none of them pay to a
+    /// signatory set with a recovery path.
+    pub fn reserve_refresh_interval(&self) -> Result<Option<u64>> {
+        let mut interval = None;
+        for index in input_signatory_set_indexes(&self.pending_utxos()?) {
+            if let Some(recovery) = self.signatory_sets.get_fixed(index)?.recovery {
+                let set_interval = recovery.refresh_interval();
+                interval = Some(interval.map_or(set_interval, |i: u64| i.min(set_interval)));
+            }
+        }
+        Ok(interval)
+    }
+
+    pub fn pending_utxos(&self) -> Result<Vec<Utxo>> {
+        self.utxos.iter().collect()
+    }
+
     pub fn active_utxos(&self) -> Result<Vec<Utxo>> {
-        // TODO: don't prune utxos, support spending from older signatory set
-        let signatory_set_index = self
-            .active_checkpoint
-            .signatory_set_index
-            .get_or_default()?;
+        self.active_checkpoint.utxos.iter().collect()
+    }
 
-        self.active_checkpoint
-            .utxos
-            .iter()
-            .filter(|utxo| match utxo {
-                Err(_) => true,
-                Ok(utxo) => utxo.signatory_set_index == signatory_set_index,
-            })
-            .collect()
+    /// Removes the pending reserve output with the given outpoint, returning
+    /// false if it is not pending.
+    pub fn remove_pending_utxo(
+        &mut self,
+        outpoint: &crate::core::bitcoin::Outpoint,
+    ) -> Result<bool> {
+        let mut removed = false;
+        for _ in 0..self.utxos.len() {
+            let utxo = self.utxos.pop_front()?.unwrap();
+            if utxo.outpoint == *outpoint {
+                removed = true;
+            } else {
+                self.utxos.push_back(utxo)?;
+            }
+        }
+        Ok(removed)
     }
 
-    pub fn active_checkpoint_tx(&self) -> Result<bitcoin::Transaction> {
-        let mut input_amount = 0;
-        let mut output_amount = 0;
+    /// Returns the position of the first signature slot belonging to the
+    /// signatory set with the given fixed index. Checkpoint signature slots are
+    /// laid out set by set, in the order given by `set_indexes`, with one slot
+    /// per signatory.
+    pub fn signature_offset(&self, set_indexes: &[u64], signatory_set_index: u64) -> Result<u64> {
+        let mut offset = 0;
+        for index in set_indexes {
+            if *index == signatory_set_index {
+                return Ok(offset);
+            }
+            offset += self.signatory_sets.get_fixed(*index)?.signatories.len() as u64;
+        }
+        bail!("Checkpoint does not spend from signatory set");
+    }
+
+    /// Returns true if every signatory set spent by the active checkpoint has
+    /// signed with more than two thirds of its voting power.
+    pub fn active_checkpoint_is_signed(&self) -> Result<bool> {
+        Ok(self.unsigned_signatory_sets()?.is_empty())
+    }
+
+    /// Returns the signatory sets spent by the active checkpoint which have not
+    /// yet signed with more than two thirds of their voting power.
+    pub fn unsigned_signatory_sets(&self) -> Result<Vec<u64>> {
+        let set_indexes = input_signatory_set_indexes(&self.active_utxos()?);
+
+        let mut unsigned = vec![];
+        let mut offset = 0;
+        for index in set_indexes {
+            let signatories = self.signatory_sets.get_fixed(index)?.signatories;
+
+            let mut signed_voting_power: u128 = 0;
+            for (i, signatory) in signatories.iter().enumerate() {
+                let slot = offset + i as u64;
+                if self.active_checkpoint.signatures.get(slot)?.is_some() {
+                    signed_voting_power += signatory.voting_power as u128;
+                }
+            }
+            if signed_voting_power <= signatories.two_thirds_voting_power() {
+                unsigned.push(index);
+            }
+
+            offset += signatories.len() as u64;
+        }
+
+        Ok(unsigned)
+    }
 
+    /// Returns true if the outputs of the signatory set are left out of
+    /// checkpoints at the current block time.
+    pub fn is_backed_off(&self, signatory_set_index: u64) -> Result<bool> {
+        let now = self.block_time.get_or_default()?;
+        Ok(match self.signing_backoffs.get(signatory_set_index)? {
+            Some(backoff) => backoff.until > now,
+            None => false,
+        })
+    }
+
+    pub fn active_checkpoint_tx(&self) -> Result<bitcoin::Transaction> {
         let signatory_set_index = self
             .active_checkpoint
             .signatory_set_index
             .get_or_default()?;
-        let signatories = self
-            .signatory_sets
-            .get_fixed(signatory_set_index)?
-            .signatories;
-
-        let inputs = self
-            .active_utxos()?
-            .into_iter()
-            .map(|utxo| {
-                input_amount += utxo.value;
-                bitcoin::TxIn {
-                    previous_output: utxo.outpoint.clone().into(),
-                    script_sig: vec![].into(),
-                    sequence: u32::MAX,
-                    witness: vec![],
-                }
-            })
-            .collect();
-
-        let mut outputs: Vec<_> = self
+        let withdrawals = self
             .active_checkpoint
             .withdrawals
             .iter()
-            .map(|w| {
-                w.map(|withdrawal| {
-                    output_amount += withdrawal.value;
-                    withdrawal.clone().into()
-                })
-            })
             .collect::<Result<_>>()?;
-
-        // TODO: calculate fee based on final tx size
-        let change_amount = input_amount - output_amount - CHECKPOINT_FEE_AMOUNT;
         let next_signatory_set = self.active_checkpoint.next_signatory_set.get_or_default()?;
-        let change_signatories = match next_signatory_set {
-            Some(next_snapshot) => next_snapshot.signatories,
-            None => signatories,
-        };
-        let change_script = crate::core::signatory_set::output_script(&change_signatories, vec![]);
-        outputs.push(bitcoin::TxOut {
-            value: change_amount,
-            script_pubkey: change_script,
-        });
+        let fee_rate = self.active_checkpoint.fee_rate.get_or_default()?;
 
-        let tx = bitcoin::Transaction {
-            version: 2,
-            lock_time: 0,
-            input: inputs,
-            output: outputs,
+        self.checkpoint_tx(
+            self.active_utxos()?,
+            withdrawals,
+            signatory_set_index,
+            next_signatory_set,
+            fee_rate,
+            None,
+        )
+    }
+
+    /// Records the active checkpoint in `checkpoints` under the current
+    /// checkpoint index, with no signatures yet.
+    pub fn record_active_checkpoint(&mut self) -> Result<()> {
+        let index = self.checkpoint_index.get_or_default()?;
+        let outputs = self
+            .active_checkpoint_tx()?
+            .output
+            .into_iter()
+            .map(|output| (output.value, output.script_pubkey.into_bytes()))
+            .collect();
+        let checkpoint = Checkpoint {
+            status: CheckpointStatus::Signing,
+            signatory_set_index: self
+                .active_checkpoint
+                .signatory_set_index
+                .get_or_default()?,
+            fee_rate: self.active_checkpoint.fee_rate.get_or_default()?,
+            inputs: self.active_utxos()?,
+            outputs,
+            signatures: vec![None; self.active_checkpoint.signatures.len() as usize],
+            tx: None,
         };
+        self.checkpoints.insert(index, checkpoint)?;
+        Ok(())
+    }
 
-        Ok(tx)
+    /// Returns the record of the checkpoint currently being signed, or the
+    /// last one if none is in progress.
+    pub fn current_checkpoint_record(&self) -> Result<Checkpoint> {
+        let index = self.checkpoint_index.get_or_default()?;
+        match self.checkpoints.get(index)? {
+            Some(checkpoint) => Ok(checkpoint),
+            None => bail!("Checkpoint record not found"),
+        }
     }
 
     pub fn has_finalized_checkpoint(&self) -> bool {
@@ -158,85 +510,275 @@ impl<S: Store> State<S> {
             bail!("No finalized checkpoint");
         }
 
-        let mut input_amount = 0;
-        let mut output_amount = 0;
-
-        let sig_set_index = self
+        let signatory_set_index = self
             .finalized_checkpoint
             .signatory_set_index
             .get_or_default()?;
-        let signatories = self.signatory_sets.get(sig_set_index)?.signatories;
-
-        let inputs = self
+        let utxos = self
             .finalized_checkpoint
             .utxos
             .iter()
-            .filter(|utxo| match utxo {
-                Err(_) => true,
-                Ok(utxo) => utxo.signatory_set_index == sig_set_index,
-            })
-            .enumerate()
-            .map(|(i, utxo)| {
-                utxo.map(|utxo| {
-                    input_amount += utxo.value;
-
-                    let mut witness: Vec<_> = self
-                        .finalized_checkpoint
-                        .signatures
-                        .iter()
-                        .collect::<Result<Vec<_>>>()? // TODO: implement DoubleEndedIterator for Deque Iter
-                        .iter()
-                        .rev()
-                        .map(|maybe_sigs| {
-                            maybe_sigs.as_ref().map_or(vec![], |sigs| {
-                                let sig = secp256k1::Signature::from_compact(&sigs[i][..]).unwrap();
-                                let mut sig = sig.serialize_der().to_vec();
-                                sig.push(
-                                    bitcoin::blockdata::transaction::SigHashType::All.as_u32()
-                                        as u8,
-                                );
-                                sig
-                            })
-                        })
-                        .collect();
-
-                    let redeem_script =
-                        crate::core::signatory_set::redeem_script(&signatories, utxo.data);
-                    witness.push(redeem_script.to_bytes());
-
-                    Ok(bitcoin::TxIn {
-                        previous_output: utxo.outpoint.clone().into(),
-                        script_sig: vec![].into(),
-                        sequence: u32::MAX,
-                        witness,
-                    })
-                })?
-            })
             .collect::<Result<_>>()?;
-
-        let mut outputs: Vec<_> = self
+        let withdrawals = self
             .finalized_checkpoint
             .withdrawals
             .iter()
-            .map(|w| {
-                w.map(|withdrawal| {
-                    output_amount += withdrawal.value;
-                    withdrawal.clone().into()
-                })
-            })
             .collect::<Result<_>>()?;
-
-        // TODO: calculate fee based on final tx size
-        let change_amount = input_amount - output_amount - CHECKPOINT_FEE_AMOUNT;
         let next_signatory_set = self
             .finalized_checkpoint
             .next_signatory_set
             .get_or_default()?;
-        let change_signatories = match next_signatory_set {
-            Some(next_snapshot) => next_snapshot.signatories,
-            None => signatories,
+        let signatures = self
+            .finalized_checkpoint
+            .signatures
+            .iter()
+            .collect::<Result<_>>()?;
+        let fee_rate = self.finalized_checkpoint.fee_rate.get_or_default()?;
+
+        self.checkpoint_tx(
+            utxos,
+            withdrawals,
+            signatory_set_index,
+            next_signatory_set,
+            fee_rate,
+            Some(signatures),
+        )
+    }
+
+    /// Compares the total NBTC `supply` against the reserve: the pending
+    /// reserve outputs and the active checkpoint, less what they still owe to
+    /// withdrawals.
+    pub fn reserve_report(&self, supply: u64) -> Result<ReserveReport> {
+        let utxos = self.pending_utxos()?;
+        let utxo_value = utxos.iter().map(|utxo| utxo.value).sum();
+        let pending_withdrawal_value = self
+            .pending_withdrawals
+            .iter()
+            .map(|withdrawal| withdrawal.map(|withdrawal| withdrawal.value))
+            .sum::<Result<_>>()?;
+
+        let active_utxos = self.active_utxos()?;
+        let (active_input_value, active_withdrawal_value, active_fee) =
+            if self.active_checkpoint.is_active.get_or_default()? {
+                let tx = self.active_checkpoint_tx()?;
+                let input_value: u64 = active_utxos.iter().map(|utxo| utxo.value).sum();
+                let output_value: u64 = tx.output.iter().map(|output| output.value).sum();
+                let change_value = tx.output.last().unwrap().value;
+                (
+                    input_value,
+                    output_value - change_value,
+                    input_value - output_value,
+                )
+            } else {
+                (0, 0, 0)
+            };
+
+        // the change output of the last finalized checkpoint should be pending
+        // or spent by the active checkpoint
+        let finalized_change = if self.has_finalized_checkpoint() {
+            let tx = self.finalized_checkpoint_tx()?;
+            let outpoint = crate::core::bitcoin::Outpoint {
+                txid: tx.txid().as_hash().into_inner(),
+                index: tx.output.len() as u32 - 1,
+            };
+            let tracked = utxos
+                .iter()
+                .chain(active_utxos.iter())
+                .any(|utxo| utxo.outpoint == outpoint);
+            Some((tx.output.last().unwrap().value, tracked))
+        } else {
+            None
         };
-        let change_script = crate::core::signatory_set::output_script(&change_signatories, vec![]);
+
+        Ok(ReserveReport {
+            supply,
+            utxo_value,
+            active_input_value,
+            active_withdrawal_value,
+            active_fee,
+            pending_withdrawal_value,
+            finalized_change,
+            withdrawal_fees: self.withdrawal_fees.get_or_default()?,
+            checkpoint_fees: self.checkpoint_fees.get_or_default()?,
+        })
+    }
+
+    /// Selects the UTXOs and withdrawals for the next checkpoint from the front
+    /// of the pending queues, which may spend from several signatory sets. The
+    /// selection stays within the `max_checkpoint_inputs`,
+    /// `max_checkpoint_outputs` and `max_checkpoint_weight` parameters, and
+    /// only includes as many withdrawals as the selected inputs can pay for
+    /// along with the fee. Outputs of signatory sets which are backed off are
+    /// skipped. Anything left over is kept queued for a later checkpoint.
+    pub fn checkpoint_selection(&self, fee_rate: u64) -> Result<(Vec<Utxo>, Vec<Withdrawal>)> {
+        let params = self.params()?;
+
+        // one output is reserved for the change
+        let mut withdrawals = vec![];
+        for withdrawal in self
+            .pending_withdrawals
+            .iter()
+            .take(params.max_checkpoint_outputs - 1)
+        {
+            withdrawals.push(withdrawal?);
+        }
+
+        let mut utxos: Vec<Utxo> = vec![];
+        let mut backed_off = BTreeMap::new();
+        for utxo in self.utxos.iter() {
+            let utxo = utxo?;
+            let index = utxo.signatory_set_index;
+            if !backed_off.contains_key(&index) {
+                backed_off.insert(index, self.is_backed_off(index)?);
+            }
+            if backed_off[&index] {
+                continue;
+            }
+            if utxos.len() == params.max_checkpoint_inputs {
+                break;
+            }
+            utxos.push(utxo);
+            if self.checkpoint_weight(&utxos, &withdrawals)? > params.max_checkpoint_weight {
+                utxos.pop();
+                break;
+            }
+        }
+
+        let input_amount: u64 = utxos.iter().map(|utxo| utxo.value).sum();
+        while !withdrawals.is_empty() {
+            let output_amount: u64 = withdrawals.iter().map(|w| w.value).sum();
+            let fee = self.checkpoint_fee(&utxos, &withdrawals, fee_rate)?;
+            if input_amount >= output_amount + fee {
+                break;
+            }
+            withdrawals.pop();
+        }
+
+        Ok((utxos, withdrawals))
+    }
+
+    /// Returns the fee for a checkpoint transaction spending `utxos` to
+    /// `withdrawals` plus a change output, at `fee_rate` satoshis per virtual
+    /// byte.
+    pub fn checkpoint_fee(
+        &self,
+        utxos: &[Utxo],
+        withdrawals: &[Withdrawal],
+        fee_rate: u64,
+    ) -> Result<u64> {
+        let weight = self.checkpoint_weight(utxos, withdrawals)?;
+        Ok((weight + 3) / 4 * fee_rate)
+    }
+
+    /// Returns the fee charged to a withdrawal paying to `script`, covering the
+    /// weight its output adds to the checkpoint transaction at the current fee
+    /// rate.
+    pub fn withdrawal_fee(&self, script: &bitcoin::Script) -> Result<u64> {
+        Ok(txout_size(script.len()) * self.fee_rate()?)
+    }
+
+    /// Estimates the weight of a fully signed checkpoint transaction, assuming
+    /// every signatory of each spent set provides a maximum-size signature.
+    fn checkpoint_weight(&self, utxos: &[Utxo], withdrawals: &[Withdrawal]) -> Result<u64> {
+        let output_count = withdrawals.len() + 1;
+
+        // version, locktime and input/output counts
+        let mut base_size = 4 + 4 + varint_len(utxos.len()) + varint_len(output_count);
+        // segwit marker and flag
+        let mut witness_size = 2;
+
+        for withdrawal in withdrawals {
+            base_size += txout_size(withdrawal.script.0.len());
+        }
+        base_size += txout_size(CHANGE_SCRIPT_SIZE);
+
+        for utxo in utxos {
+            // outpoint, empty script_sig and sequence
+            base_size += 36 + 1 + 4;
+
+            let snapshot = self.signatory_sets.get_fixed(utxo.signatory_set_index)?;
+            let signatory_count = snapshot.signatories.len();
+            let redeem_script_size = snapshot.redeem_script(utxo.data.clone()).len();
+
+            let mut item_count = signatory_count + 1;
+            if snapshot.recovery.is_some() {
+                // branch selector
+                item_count += 1;
+                witness_size += 2;
+            }
+            witness_size += varint_len(item_count);
+            witness_size += signatory_count as u64 * (1 + MAX_SIGNATURE_SIZE);
+            witness_size += varint_len(redeem_script_size) + redeem_script_size as u64;
+        }
+
+        Ok(base_size * 4 + witness_size)
+    }
+
+    /// Builds a checkpoint transaction spending `utxos` to `withdrawals`, with the
+    /// change paid to the next signatory set (or the checkpoint's own set if it
+    /// is not changing). The fee is estimated from the signed weight of the
+    /// transaction at `fee_rate`. If `signatures` is given, each input's witness
+    /// is filled in with the signatures of the signatory set that input pays to.
+    fn checkpoint_tx(
+        &self,
+        utxos: Vec<Utxo>,
+        withdrawals: Vec<Withdrawal>,
+        signatory_set_index: u64,
+        next_signatory_set: Option<SignatorySetSnapshot>,
+        fee_rate: u64,
+        signatures: Option<Vec<Option<Vec<Signature>>>>,
+    ) -> Result<bitcoin::Transaction> {
+        let mut input_amount = 0;
+        let mut output_amount = 0;
+        let fee = self.checkpoint_fee(&utxos, &withdrawals, fee_rate)?;
+
+        let set_indexes = input_signatory_set_indexes(&utxos);
+        let mut input_counts = vec![0; set_indexes.len()];
+
+        let mut inputs = Vec::with_capacity(utxos.len());
+        for utxo in utxos {
+            input_amount += utxo.value;
+
+            let witness = match signatures {
+                None => vec![],
+                Some(ref signatures) => {
+                    // position of this input among the inputs paying to the same set
+                    let set_position = set_indexes
+                        .iter()
+                        .position(|index| *index == utxo.signatory_set_index)
+                        .unwrap();
+                    let input_position = input_counts[set_position];
+                    input_counts[set_position] += 1;
+
+                    self.input_witness(&utxo, &set_indexes, input_position, signatures)?
+                }
+            };
+
+            inputs.push(bitcoin::TxIn {
+                previous_output: utxo.outpoint.into(),
+                script_sig: vec![].into(),
+                sequence: u32::MAX,
+                witness,
+            });
+        }
+
+        let mut outputs: Vec<_> = withdrawals
+            .into_iter()
+            .map(|withdrawal| {
+                output_amount += withdrawal.value;
+                withdrawal.into()
+            })
+            .collect();
+
+        let change_amount = match input_amount.checked_sub(output_amount + fee) {
+            Some(change_amount) => change_amount,
+            None => bail!("Checkpoint inputs do not cover outputs and fee"),
+        };
+        let change_snapshot = match next_signatory_set {
+            Some(next_snapshot) => next_snapshot,
+            None => self.signatory_sets.get_fixed(signatory_set_index)?,
+        };
+        let change_script = change_snapshot.output_script(vec![]);
         outputs.push(bitcoin::TxOut {
             value: change_amount,
             script_pubkey: change_script,
@@ -251,4 +793,125 @@ impl<S: Store> State<S> {
 
         Ok(tx)
     }
+
+    /// Builds the witness for a checkpoint input from the signatures of the
+    /// signatory set it pays to. `input_position` is the index of the input
+    /// among the checkpoint inputs paying to that same set.
+    fn input_witness(
+        &self,
+        utxo: &Utxo,
+        set_indexes: &[u64],
+        input_position: usize,
+        signatures: &[Option<Vec<Signature>>],
+    ) -> Result<Vec<Vec<u8>>> {
+        let snapshot = self.signatory_sets.get_fixed(utxo.signatory_set_index)?;
+        let offset = self.signature_offset(set_indexes, utxo.signatory_set_index)? as usize;
+
+        let mut witness: Vec<_> = (0..snapshot.signatories.len())
+            .rev()
+            .map(|i| {
+                signatures[offset + i].as_ref().map_or(vec![], |sigs| {
+                    let sig =
+                        secp256k1::Signature::from_compact(&sigs[input_position][..]).unwrap();
+                    let mut sig = sig.serialize_der().to_vec();
+                    sig.push(bitcoin::blockdata::transaction::SigHashType::All.as_u32() as u8);
+                    sig
+                })
+            })
+            .collect();
+
+        if snapshot.recovery.is_some() {
+            // select the signatory branch over the recovery branch
+            witness.push(vec![1]);
+        }
+        witness.push(snapshot.redeem_script(utxo.data.clone()).to_bytes());
+
+        Ok(witness)
+    }
+}
+
+/// Size of a DER-encoded signature with its sighash type byte, at most.
+const MAX_SIGNATURE_SIZE: u64 = 73;
+
+/// Size of the P2WSH script the checkpoint change is paid to.
+const CHANGE_SCRIPT_SIZE: usize = 34;
+
+fn varint_len(n: usize) -> u64 {
+    match n {
+        0..=0xfc => 1,
+        0xfd..=0xffff => 3,
+        0x10000..=0xffff_ffff => 5,
+        _ => 9,
+    }
+}
+
+/// Weight of a signed checkpoint spending a single deposit output of the
+/// largest possible signatory set, with the largest recovery path, to the
+/// change output. A lower `max_checkpoint_weight` parameter could leave such
+/// outputs unspendable, stopping checkpoints for good.
+pub fn min_checkpoint_weight() -> u64 {
+    let signatories = MAX_SIGNATORIES as u64;
+    // each signatory's pubkey push, voting power push and 5 opcodes, then the
+    // two thirds push and OP_GREATERTHAN
+    let quorum_size = signatories * (34 + 4 + 5) + 5;
+    // OP_IF, OP_ELSE and OP_ENDIF, the timelock with OP_CSV and OP_DROP, the
+    // threshold, the keys and their count with OP_CHECKMULTISIG
+    let recovery_size = 3 + 4 + 2 + 2 + MAX_RECOVERY_KEYS as u64 * 34 + 2 + 1;
+    // recipient push and OP_DROP
+    let data_size = 1 + 33 + 1;
+    let redeem_script_size = quorum_size + recovery_size + data_size;
+
+    let base_size = 4 + 4 + 1 + 1 + txout_size(CHANGE_SCRIPT_SIZE) + 36 + 1 + 4;
+    let item_count = signatories as usize + 2;
+    let witness_size = 2
+        + varint_len(item_count)
+        + 2
+        + signatories * (1 + MAX_SIGNATURE_SIZE)
+        + varint_len(redeem_script_size as usize)
+        + redeem_script_size;
+    base_size * 4 + witness_size
+}
+
+/// Serialized size of a transaction output with a script of the given length.
+fn txout_size(script_len: usize) -> u64 {
+    8 + varint_len(script_len) + script_len as u64
+}
+
+/// Returns true if `script` is a standard withdrawal destination: P2PKH, P2SH,
+/// P2WPKH or P2WSH. Other scripts could make a checkpoint transaction
+/// non-standard and unrelayable.
+pub fn is_standard_withdrawal_script(script: &bitcoin::Script) -> bool {
+    script.is_p2pkh() || script.is_p2sh() || script.is_v0_p2wpkh() || script.is_v0_p2wsh()
+}
+
+/// Returns the smallest value an output paying to `script` may carry without
+/// being considered dust by Bitcoin Core's default relay policy, i.e. the cost
+/// of creating and later spending the output at 3 satoshis per virtual byte.
+/// This gives 546 for P2PKH, 540 for P2SH, 294 for P2WPKH and 330 for P2WSH.
+pub fn dust_limit(script: &bitcoin::Script) -> u64 {
+    let spend_size = if is_witness_program(script.as_bytes()) {
+        // outpoint, script_sig length, sequence and a discounted signature
+        32 + 4 + 1 + 107 / 4 + 4
+    } else {
+        32 + 4 + 1 + 107 + 4
+    };
+    (txout_size(script.len()) + spend_size) * 3
+}
+
+fn is_witness_program(script: &[u8]) -> bool {
+    if script.len() < 4 || script.len() > 42 {
+        return false;
+    }
+    let version_op = script[0];
+    if version_op != 0x00 && (version_op < 0x51 || version_op > 0x60) {
+        return false;
+    }
+    script[1] as usize == script.len() - 2
+}
+
+/// Returns the fixed indexes of the signatory sets the given UTXOs pay to, in
+/// ascending order. A checkpoint collects one group of signatures per set.
+pub fn input_signatory_set_indexes(utxos: &[Utxo]) -> Vec<u64> {
+    let indexes: BTreeSet<u64> = utxos.iter().map(|utxo| utxo.signatory_set_index).collect();
+    indexes.into_iter().collect()
 }
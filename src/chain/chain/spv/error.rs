@@ -36,6 +36,12 @@ pub enum Error {
     UnconnectedHeader,
     /// no chain tip found
     NoTip,
+    /// header time is not after the median time of the previous blocks
+    SpvTimeTooOld,
+    /// header time is too far ahead of the current time
+    SpvTimeTooNew,
+    /// header version is below the minimum for its height
+    SpvBadVersion,
     /// no peers to connect to
     NoPeers,
     /// unknown UTXO referred
@@ -68,6 +74,9 @@ impl std::error::Error for Error {
             Error::SpvBadProofOfWork => "bad proof of work",
             Error::UnconnectedHeader => "unconnected header",
             Error::NoTip => "no chain tip found",
+            Error::SpvTimeTooOld => "header time is not after the median time past",
+            Error::SpvTimeTooNew => "header time is too far in the future",
+            Error::SpvBadVersion => "obsolete header version",
             Error::UnknownUTXO => "unknown utxo",
             Error::NoPeers => "no peers",
             Error::BadMerkleRoot => "merkle root of header does not match transaction list",
@@ -86,6 +95,9 @@ impl std::error::Error for Error {
             Error::SpvBadProofOfWork => None,
             Error::UnconnectedHeader => None,
             Error::NoTip => None,
+            Error::SpvTimeTooOld => None,
+            Error::SpvTimeTooNew => None,
+            Error::SpvBadVersion => None,
             Error::NoPeers => None,
             Error::UnknownUTXO => None,
             Error::Downstream(_) => None,
@@ -112,6 +124,9 @@ impl fmt::Display for Error {
             | Error::BadMerkleRoot
             | Error::Handshake
             | Error::UnknownUTXO => write!(f, "{}", self),
+            Error::SpvTimeTooOld => write!(f, "header time is not after the median time past"),
+            Error::SpvTimeTooNew => write!(f, "header time is too far in the future"),
+            Error::SpvBadVersion => write!(f, "obsolete header version"),
             Error::Lost(ref s) | Error::Downstream(ref s) => write!(f, "{}", s),
             Error::IO(ref err) => write!(f, "IO error: {}", err),
             Error::Util(ref err) => write!(f, "Util error: {}", err),
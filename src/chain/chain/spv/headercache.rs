@@ -18,11 +18,10 @@
 //!
 
 use super::error::Error;
-use crate::core::bitcoin::bitcoin;
+use crate::core::bitcoin::{bitcoin, Network};
 use bitcoin::hash_types::BlockHash as Sha256dHash;
 use bitcoin::{
-    blockdata::block::BlockHeader, hashes as bitcoin_hashes, network::constants::Network,
-    util::uint::Uint256, BitcoinHash,
+    blockdata::block::BlockHeader, hashes as bitcoin_hashes, util::uint::Uint256, BitcoinHash,
 };
 use bitcoin_hashes::Hash;
 use failure::bail;
@@ -135,13 +134,15 @@ impl BitcoinHash<Sha256dHash> for CachedHeader {
     }
 }
 
+/// Validates Bitcoin headers and stores the trunk, the header chain with most
+/// work, along with the recent forks from it.
 pub struct HeaderCache<'a> {
     // network
     network: Network,
-    // header chain with most work
-    pub trunk: Vec<Sha256dHash>,
     // orga store to use instead of headers hashmap
     store: &'a mut dyn Store,
+    // current time, for rejecting headers from the future
+    time: Option<u64>,
 }
 
 impl<'a> HeaderCache<'a> {
@@ -149,17 +150,34 @@ impl<'a> HeaderCache<'a> {
         HeaderCache {
             network,
             store,
-            trunk: Vec::new(),
+            time: None,
         }
     }
 
+    /// Sets the current time, in seconds since the Unix epoch. Once set, added
+    /// headers may not be more than two hours ahead of it.
+    pub fn set_time(&mut self, time: u64) {
+        self.time = Some(time);
+    }
+
     /// Adds a trusted header without any verification.
     ///
     /// Useful for configuring the SPV to work from some checkpoint sufficiently deep in the
     /// past.
     pub fn add_header_raw(&mut self, header: BlockHeader, height: u32) -> Result<(), Error> {
+        self.add_header_raw_with_work(header, height, header.work())
+    }
+
+    /// Adds a trusted header without any verification, with `work` being the
+    /// total work of the chain up to and including it.
+    pub fn add_header_raw_with_work(
+        &mut self,
+        header: BlockHeader,
+        height: u32,
+        work: Uint256,
+    ) -> Result<(), Error> {
         let stored = StoredHeader {
-            work_bytes: header.work().to_bytes(),
+            work_bytes: work.to_bytes(),
             header,
             height,
         };
@@ -170,7 +188,6 @@ impl<'a> HeaderCache<'a> {
         id: &Sha256dHash,
         stored: &StoredHeader,
     ) -> Result<(), Error> {
-        self.load_trunk();
         let cached = CachedHeader::new(id, stored.clone());
         let result = self.insert_header(id.clone(), cached);
 
@@ -178,12 +195,21 @@ impl<'a> HeaderCache<'a> {
             return Err(header_insert_err.into());
         }
 
-        self.trunk.push(id.clone());
-        self.save_trunk()?;
+        self.set_trunk_hash(stored.height, id)?;
+        self.set_tip_height(stored.height)?;
+        if self.read_height(PRUNED_KEY)?.is_none() {
+            self.write_height(PRUNED_KEY, stored.height)?;
+        }
         Ok(())
     }
 
     /// add a Bitcoin header
+    ///
+    /// Headers forking from more than `FINALITY_DEPTH` blocks below the tip are
+    /// accepted, but are pruned the next time the tip changes unless their
+    /// fork has become the trunk by then. On signet, blocks must also satisfy the network's challenge
+    /// script, but the block signature is committed to in the coinbase
+    /// transaction rather than in the header, so it is not verified here.
     pub fn add_header(
         &mut self,
         header: &BlockHeader,
@@ -194,7 +220,6 @@ impl<'a> HeaderCache<'a> {
             Option<Vec<Sha256dHash>>,
         )>,
     > {
-        self.load_trunk();
         if self.get_header(&header.bitcoin_hash())?.is_some() {
             // ignore already known header
             return Ok(None);
@@ -221,9 +246,10 @@ impl<'a> HeaderCache<'a> {
                     work_bytes: header.work().to_bytes(),
                 },
             );
-            self.trunk.push(new_tip.clone());
             self.insert_header(new_tip.clone(), stored.clone())?;
-            self.save_trunk()?;
+            self.set_trunk_hash(0, &new_tip)?;
+            self.set_tip_height(0)?;
+            self.write_height(PRUNED_KEY, 0)?;
             return Ok(Some((stored, None, Some(vec![new_tip]))));
         }
     }
@@ -246,30 +272,182 @@ impl<'a> HeaderCache<'a> {
         }
     }
 
-    /// Load and deserialize trunk from store.
-    pub fn load_trunk(&mut self) -> Option<&Vec<Sha256dHash>> {
-        self.store
-            .get(b"trunk")
-            .expect("Failed to get trunk from store")
-            .map(move |trunk_bytes| {
-                let trunk =
-                    bytes_to_hashes(trunk_bytes.as_slice()).expect("Failed to read trunk hashes");
-                self.trunk = trunk;
-                &self.trunk
-            })
+    /// Returns the hash of the trunk header at `height`, if any.
+    fn trunk_hash(&self, height: u32) -> OrgaResult<Option<Sha256dHash>> {
+        match self.store.get(trunk_key(height).as_slice())? {
+            Some(bytes) => Ok(Some(Sha256dHash::from_slice(bytes.as_slice())?)),
+            None => Ok(None),
+        }
+    }
+
+    fn set_trunk_hash(&mut self, height: u32, hash: &Sha256dHash) -> OrgaResult<()> {
+        self.store.put(trunk_key(height), hash.to_vec())
+    }
+
+    fn read_height(&self, key: &[u8]) -> OrgaResult<Option<u32>> {
+        match self.store.get(key)? {
+            Some(bytes) => {
+                let mut height = [0; 4];
+                height.copy_from_slice(bytes.as_slice());
+                Ok(Some(u32::from_be_bytes(height)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn write_height(&mut self, key: &[u8], height: u32) -> OrgaResult<()> {
+        self.store.put(key.to_vec(), height.to_be_bytes().to_vec())
+    }
+
+    /// Returns the height of the header with most work, if any.
+    fn tip_height(&self) -> OrgaResult<Option<u32>> {
+        self.read_height(TIP_KEY)
+    }
+
+    fn set_tip_height(&mut self, height: u32) -> OrgaResult<()> {
+        self.write_height(TIP_KEY, height)
     }
 
-    /// Serialize and save current trunk to store.
-    fn save_trunk(&mut self) -> OrgaResult<()> {
-        if self.trunk.len() > 2018 {
-            self.trunk = self.trunk.drain((self.trunk.len() - 2018)..).collect();
+    /// Records `hash` among the headers stored at `height`, so the ones which
+    /// end up off the trunk can be found when pruning. A height which was
+    /// already pruned is pruned again.
+    fn index_header(&mut self, height: u32, hash: &Sha256dHash) -> OrgaResult<()> {
+        let key = height_index_key(height);
+        let mut hashes = self.store.get(key.as_slice())?.unwrap_or_default();
+        hashes.extend(&hash.into_inner());
+        self.store.put(key, hashes)?;
+
+        match self.read_height(PRUNED_KEY)? {
+            Some(pruned) if height <= pruned => self.write_height(PRUNED_KEY, height - 1),
+            _ => Ok(()),
         }
-        let trunk_bytes = hashes_to_bytes(&self.trunk);
-        self.store.put(b"trunk".to_vec(), trunk_bytes)
     }
 
-    fn max_target() -> Uint256 {
-        Uint256::from_u64(0xFFFF).unwrap() << 208
+    /// Deletes the headers off the trunk at every height which is at least
+    /// `FINALITY_DEPTH` below `tip_height` and has not been pruned yet, so fork
+    /// headers can't grow the state without bound.
+    fn prune(&mut self, tip_height: u32) -> OrgaResult<()> {
+        if tip_height < FINALITY_DEPTH {
+            return Ok(());
+        }
+        let end = tip_height - FINALITY_DEPTH;
+        let start = match self.read_height(PRUNED_KEY)? {
+            Some(pruned) => pruned + 1,
+            None => end,
+        };
+
+        for height in start..=end {
+            let key = height_index_key(height);
+            let hashes = match self.store.get(key.as_slice())? {
+                Some(bytes) => bytes_to_hashes(bytes.as_slice())?,
+                None => continue,
+            };
+            let trunk_hash = self.trunk_hash(height)?;
+            for hash in hashes {
+                if Some(hash) != trunk_hash {
+                    self.store.delete(hash.to_vec().as_slice())?;
+                }
+            }
+            self.store.delete(key.as_slice())?;
+        }
+
+        if start <= end {
+            self.write_height(PRUNED_KEY, end)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the hashes of up to `count` trunk headers ending at the tip,
+    /// ordered by height.
+    pub fn trunk_hashes(&mut self, count: u32) -> OrgaResult<Vec<Sha256dHash>> {
+        let tip_height = match self.tip_height()? {
+            Some(height) => height,
+            None => return Ok(vec![]),
+        };
+
+        let mut hashes = vec![];
+        let mut height = tip_height;
+        while hashes.len() < count as usize {
+            match self.trunk_hash(height)? {
+                Some(hash) => hashes.push(hash),
+                None => break,
+            }
+            if height == 0 {
+                break;
+            }
+            height -= 1;
+        }
+        hashes.reverse();
+        Ok(hashes)
+    }
+
+    /// The easiest target allowed on the network, its difficulty 1. Signet has
+    /// its own limit but otherwise follows mainnet's difficulty rules.
+    fn max_target(&self) -> Uint256 {
+        match self.network {
+            // 0x1e0377ae in compact form
+            Network::Signet => Uint256::from_u64(0x0377ae).unwrap() << 216,
+            // 0x207fffff in compact form
+            Network::Regtest => Uint256::from_u64(0x7fffff).unwrap() << 232,
+            _ => Uint256::from_u64(0xFFFF).unwrap() << 208,
+        }
+    }
+
+    /// The minimum header version at `height`, once BIP34, BIP66 and BIP65
+    /// are active.
+    fn min_version(&self, height: u32) -> u32 {
+        let (bip34, bip66, bip65) = match self.network {
+            Network::Bitcoin => (227_931, 363_725, 388_381),
+            Network::Testnet => (21_111, 330_776, 581_885),
+            Network::Regtest | Network::Signet => (1, 1, 1),
+        };
+        if height >= bip65 {
+            4
+        } else if height >= bip66 {
+            3
+        } else if height >= bip34 {
+            2
+        } else {
+            0
+        }
+    }
+
+    /// The median time of `prev` and up to ten of its ancestors.
+    fn median_time_past(&self, prev: &CachedHeader) -> OrgaResult<u32> {
+        const MEDIAN_TIME_SPAN: usize = 11;
+        let mut times = vec![prev.stored.header.time];
+        let mut hash = prev.stored.header.prev_blockhash;
+        while times.len() < MEDIAN_TIME_SPAN {
+            match self.get_header(&hash)? {
+                Some(header) => {
+                    times.push(header.stored.header.time);
+                    hash = header.stored.header.prev_blockhash;
+                }
+                None => break,
+            }
+        }
+        times.sort_unstable();
+        Ok(times[times.len() / 2])
+    }
+
+    /// Checks the header rules which don't depend on proof of work.
+    fn check_header(&self, prev: &CachedHeader, next: &BlockHeader) -> Result<(), Error> {
+        const MAX_FUTURE_BLOCK_TIME: u64 = 2 * 60 * 60;
+
+        if next.time <= self.median_time_past(prev)? {
+            return Err(Error::SpvTimeTooOld);
+        }
+        if let Some(time) = self.time {
+            if next.time as u64 > time + MAX_FUTURE_BLOCK_TIME {
+                return Err(Error::SpvTimeTooNew);
+            }
+        }
+        // versions are signed in Bitcoin Core, so the high bit makes them negative
+        let version = next.version as i32;
+        if version < self.min_version(prev.stored.height + 1) as i32 {
+            return Err(Error::SpvBadVersion);
+        }
+        Ok(())
     }
 
     // add header to tree, return stored, optional list of unwinds, optional list of extensions
@@ -288,15 +466,24 @@ impl<'a> HeaderCache<'a> {
         const DIFFCHANGE_INTERVAL: u32 = 2016;
         const DIFFCHANGE_TIMESPAN: u32 = 14 * 24 * 3600;
         const TARGET_BLOCK_SPACING: u32 = 600;
-        self.load_trunk();
+        self.check_header(prev, next)?;
         let required_work =
+        // Regtest never retargets. Its minimum-difficulty rule resets to the
+        // genesis difficulty, which is already the easiest allowed, so every
+        // block keeps the previous block's difficulty.
+            if self.network == Network::Regtest {
+                prev.stored.header.target()
         // Compute required difficulty if this is a diffchange block
-            if (prev.stored.height + 1) % DIFFCHANGE_INTERVAL == 0 {
+            } else if (prev.stored.height + 1) % DIFFCHANGE_INTERVAL == 0 {
                 let timespan = {
                     // Scan back DIFFCHANGE_INTERVAL blocks
                     let mut scan = prev.clone();
-                    if self.tip_hash() == Some(scan.stored.header.prev_blockhash) {
-                        scan = self.get_header(&self.trunk[self.trunk.len() - DIFFCHANGE_INTERVAL as usize - 2])?.unwrap().clone();
+                    if self.pos_on_trunk(&prev.bitcoin_hash())?.is_some() {
+                        let height = prev.stored.height - (DIFFCHANGE_INTERVAL - 1);
+                        match self.get_header_for_height(height)? {
+                            Some(header) => scan = header,
+                            None => return Err(Error::UnconnectedHeader),
+                        }
                     } else {
                         for _ in 0..(DIFFCHANGE_INTERVAL - 1) {
                             if let Some(header) = self.get_header(&scan.stored.header.prev_blockhash)? {
@@ -318,7 +505,7 @@ impl<'a> HeaderCache<'a> {
                 target = target.mul_u32(timespan);
                 target = target / Uint256::from_u64(DIFFCHANGE_TIMESPAN as u64).unwrap();
                 // Clamp below MAX_TARGET (difficulty 1)
-                let max = Self::max_target();
+                let max = self.max_target();
                 if target > max { target = max };
                 // Compactify (make expressible in the 8+24 nBits float format)
                 Self::satoshi_the_precision(target)
@@ -326,7 +513,7 @@ impl<'a> HeaderCache<'a> {
                 // block interval resets the difficulty to 1
             } else if self.network == Network::Testnet &&
                 next.time > prev.stored.header.time + 2 * TARGET_BLOCK_SPACING {
-                Self::max_target()
+                self.max_target()
                 // On the other hand, if we are in Testnet and the block interval is less
                 // than 20 minutes, we need to scan backward to find a block for which the
                 // previous rule did not apply, to find the "real" difficulty.
@@ -334,7 +521,7 @@ impl<'a> HeaderCache<'a> {
                 // Scan back DIFFCHANGE_INTERVAL blocks
                 let mut scan = prev.clone();
                 let mut height = prev.stored.height;
-                let max_target = Self::max_target();
+                let max_target = self.max_target();
                 while height % DIFFCHANGE_INTERVAL != 0 && scan.stored.header.prev_blockhash != Sha256dHash::default() && scan.stored.header.target() == max_target {
                     if let Some(header) = self.get_header(&scan.stored.header.prev_blockhash)? {
                         scan = header.clone();
@@ -370,6 +557,7 @@ impl<'a> HeaderCache<'a> {
         if let Err(e) = result {
             return Err(Error::Downstream(format!("{}", e)));
         }
+        self.index_header(cached.stored.height, &next_hash)?;
         if let Some(tip) = self.tip()? {
             if tip.stored.work() < cached.stored.work() {
                 // higher POW than previous tip
@@ -377,10 +565,10 @@ impl<'a> HeaderCache<'a> {
                 // compute path to new tip
                 let mut forks_at = next.prev_blockhash;
                 let mut path_to_new_tip = Vec::new();
-                while self.pos_on_trunk(&forks_at).is_none() {
+                while self.pos_on_trunk(&forks_at)?.is_none() {
                     if let Some(h) = self.get_header(&forks_at)? {
-                        forks_at = h.stored.header.prev_blockhash;
                         path_to_new_tip.push(forks_at);
+                        forks_at = h.stored.header.prev_blockhash;
                     } else {
                         return Err(Error::UnconnectedHeader);
                     }
@@ -389,60 +577,67 @@ impl<'a> HeaderCache<'a> {
                 path_to_new_tip.push(next_hash);
 
                 // compute list of headers no longer on trunk
-                if forks_at != next.prev_blockhash {
-                    let mut unwinds = Vec::new();
-
-                    if let Some(pos) = self.trunk.iter().rposition(|h| *h == forks_at) {
-                        if pos < self.trunk.len() - 1 {
-                            // store and cut headers that are no longer on trunk
-                            unwinds.extend(self.trunk[pos + 1..].iter().rev().map(|h| *h));
-                            self.trunk.truncate(pos + 1);
+                let fork_height = match self.pos_on_trunk(&forks_at)? {
+                    Some(height) => height,
+                    None => return Err(Error::UnconnectedHeader),
+                };
+                let pruned = self.read_height(PRUNED_KEY)?;
+                let mut unwinds = Vec::new();
+                for height in ((fork_height + 1)..=tip.stored.height).rev() {
+                    if let Some(hash) = self.trunk_hash(height)? {
+                        // pruned heights no longer index their trunk header
+                        if pruned.map_or(false, |pruned| height <= pruned) {
+                            self.index_header(height, &hash)?;
                         }
-                    } else {
-                        return Err(Error::UnconnectedHeader);
+                        unwinds.push(hash);
                     }
-                    self.trunk.extend(path_to_new_tip.iter().map(|h| *h));
-                    self.save_trunk()?;
-                    return Ok((cached, Some(unwinds), Some(path_to_new_tip)));
-                } else {
-                    self.trunk.extend(path_to_new_tip.iter().map(|h| *h));
-                    self.save_trunk()?;
+                    self.store.delete(trunk_key(height).as_slice())?;
+                }
+
+                for (height, hash) in ((fork_height + 1)..).zip(path_to_new_tip.iter()) {
+                    self.set_trunk_hash(height, hash)?;
+                }
+                self.set_tip_height(cached.stored.height)?;
+                self.prune(cached.stored.height)?;
+                if unwinds.is_empty() {
                     return Ok((cached, None, Some(path_to_new_tip)));
+                } else {
+                    return Ok((cached, Some(unwinds), Some(path_to_new_tip)));
                 }
             } else {
-                self.save_trunk()?;
                 return Ok((cached, None, None));
             }
         } else {
-            self.save_trunk()?;
             return Err(Error::NoTip);
         }
     }
 
     /// position on trunk (chain with most work from genesis to tip)
-    pub fn pos_on_trunk(&mut self, hash: &Sha256dHash) -> Option<u32> {
-        self.load_trunk();
-        self.trunk
-            .iter()
-            .rev()
-            .position(|e| *e == *hash)
-            .map(|p| (self.trunk.len() - p - 1) as u32)
+    pub fn pos_on_trunk(&mut self, hash: &Sha256dHash) -> OrgaResult<Option<u32>> {
+        let height = match self.get_header(hash)? {
+            Some(header) => header.stored.height,
+            None => return Ok(None),
+        };
+        if self.trunk_hash(height)?.as_ref() == Some(hash) {
+            Ok(Some(height))
+        } else {
+            Ok(None)
+        }
     }
 
     /// retrieve the id of the block/header with most work
     pub fn tip(&mut self) -> OrgaResult<Option<CachedHeader>> {
-        if let Some(id) = self.tip_hash() {
+        if let Some(id) = self.tip_hash()? {
             return Ok(self.get_header(&id)?);
         }
         Ok(None)
     }
 
-    pub fn tip_hash(&mut self) -> Option<Sha256dHash> {
-        self.load_trunk();
-        if let Some(tip) = self.trunk.last() {
-            return Some(*tip);
+    pub fn tip_hash(&mut self) -> OrgaResult<Option<Sha256dHash>> {
+        match self.tip_height()? {
+            Some(height) => self.trunk_hash(height),
+            None => Ok(None),
         }
-        None
     }
 
     /// taken from an early rust-bitcoin by Andrew Poelstra:
@@ -463,25 +658,41 @@ impl<'a> HeaderCache<'a> {
         ret << bits
     }
 
+    /// Returns the trunk header at `height`. The trunk is stored as one entry
+    /// per height plus a pointer to the tip's height, so extending or reorging
+    /// it only writes the entries which change.
     pub fn get_header_for_height(&mut self, height: u32) -> OrgaResult<Option<CachedHeader>> {
-        self.load_trunk();
-        let maybe_tip = self.tip()?;
-        let tip = match maybe_tip {
-            Some(cached_header) => cached_header.stored,
-            None => return Ok(None),
-        };
-
-        let start_height = tip.height - (self.trunk.len() - 1) as u32;
-        if height < start_height {
-            Ok(None)
-        } else if (height - start_height) < self.trunk.len() as u32 {
-            Ok(self.get_header(&self.trunk[(height - start_height) as usize])?)
-        } else {
-            Ok(None)
+        match self.trunk_hash(height)? {
+            Some(hash) => self.get_header(&hash),
+            None => Ok(None),
         }
     }
 }
 
+/// Number of blocks below the tip after which headers off the trunk are pruned,
+/// about a day of blocks. Deeper forks are still accepted, but a fork's headers
+/// must be relayed before the trunk advances again or they have to be resent.
+pub const FINALITY_DEPTH: u32 = 144;
+
+const TIP_KEY: &[u8] = b"tip";
+/// Key of the height up to which headers off the trunk have been pruned.
+const PRUNED_KEY: &[u8] = b"pruned";
+
+/// Store key of the trunk entry at `height`. Header entries are keyed by their
+/// 32-byte hash, so this and the other shorter keys can't collide with them.
+fn trunk_key(height: u32) -> Vec<u8> {
+    let mut key = b"trunk/".to_vec();
+    key.extend(&height.to_be_bytes());
+    key
+}
+
+/// Store key of the hashes of all headers added at `height`.
+fn height_index_key(height: u32) -> Vec<u8> {
+    let mut key = b"height/".to_vec();
+    key.extend(&height.to_be_bytes());
+    key
+}
+
 pub fn bytes_to_hashes(bytes: &[u8]) -> Result<Vec<Sha256dHash>, failure::Error> {
     if bytes.len() % 32 != 0 {
         bail!("Byte length should be a multiple of 32");
@@ -509,6 +720,354 @@ where
 mod tests {
     use super::*;
     use crate::core::bitcoin::bitcoin::hash_types::BlockHash as Sha256dHash;
+    use orga::MapStore;
+
+    fn mine(header: &mut BlockHeader) {
+        while header.validate_pow(&header.target()).is_err() {
+            header.nonce += 1;
+        }
+    }
+
+    #[test]
+    fn max_targets() {
+        let mut store = MapStore::new();
+        for network in [
+            Network::Bitcoin,
+            Network::Testnet,
+            Network::Regtest,
+            Network::Signet,
+        ]
+        .iter()
+        {
+            let cache = HeaderCache::new(*network, &mut store);
+            assert_eq!(cache.max_target(), network.genesis_header().target());
+        }
+    }
+
+    #[test]
+    fn signet_has_no_min_difficulty_rule() {
+        let start = BlockHeader {
+            version: 0x2000_0000,
+            prev_blockhash: Default::default(),
+            merkle_root: Default::default(),
+            time: 1_600_000_000,
+            bits: 0x207fffff,
+            nonce: 0,
+        };
+        // more than 20 minutes after the previous block
+        let mut next = BlockHeader {
+            prev_blockhash: start.bitcoin_hash(),
+            time: start.time + 1201,
+            ..start
+        };
+        mine(&mut next);
+
+        // testnet requires difficulty 1 after a 20 minute gap
+        let mut store = MapStore::new();
+        let mut cache = HeaderCache::new(Network::Testnet, &mut store);
+        cache.add_header_raw(start, 100).unwrap();
+        assert!(cache.add_header(&next).is_err());
+
+        // signet keeps the previous difficulty
+        let mut store = MapStore::new();
+        let mut cache = HeaderCache::new(Network::Signet, &mut store);
+        cache.add_header_raw(start, 100).unwrap();
+        cache.add_header(&next).unwrap();
+        assert_eq!(cache.tip().unwrap().unwrap().stored.height, 101);
+    }
+
+    #[test]
+    fn regtest_never_retargets() {
+        let genesis = Network::Regtest.genesis_header();
+        let mut store = MapStore::new();
+        let mut cache = HeaderCache::new(Network::Regtest, &mut store);
+        cache.add_header_raw(genesis, 0).unwrap();
+
+        // mine past the first two retarget boundaries, one second apart
+        let chain = mine_chain(&genesis, 2 * 2016 + 100, 1);
+        for header in chain.iter() {
+            cache.add_header(header).unwrap();
+        }
+        let prev = *chain.last().unwrap();
+
+        let tip = cache.tip().unwrap().unwrap();
+        assert_eq!(tip.stored.height, 2 * 2016 + 100);
+        assert_eq!(tip.stored.header, prev);
+        assert_eq!(tip.stored.header.bits, genesis.bits);
+    }
+
+    fn mine_chain(prev: &BlockHeader, count: usize, time_offset: u32) -> Vec<BlockHeader> {
+        let mut prev = *prev;
+        let mut headers = vec![];
+        for _ in 0..count {
+            let mut next = BlockHeader {
+                prev_blockhash: prev.bitcoin_hash(),
+                time: prev.time + time_offset,
+                nonce: 0,
+                ..prev
+            };
+            mine(&mut next);
+            headers.push(next);
+            prev = next;
+        }
+        headers
+    }
+
+    #[test]
+    fn trunk_by_height() {
+        let genesis = Network::Regtest.genesis_header();
+        let mut store = MapStore::new();
+        let mut cache = HeaderCache::new(Network::Regtest, &mut store);
+        cache.add_header_raw(genesis, 0).unwrap();
+
+        let chain = mine_chain(&genesis, 3, 1);
+        for header in chain.iter() {
+            cache.add_header(header).unwrap();
+        }
+        assert_eq!(cache.tip().unwrap().unwrap().stored.height, 3);
+        for (i, header) in chain.iter().enumerate() {
+            let stored = cache.get_header_for_height(i as u32 + 1).unwrap().unwrap();
+            assert_eq!(stored.stored.header, *header);
+            assert_eq!(
+                cache.pos_on_trunk(&header.bitcoin_hash()).unwrap(),
+                Some(i as u32 + 1)
+            );
+        }
+        assert!(cache.get_header_for_height(4).unwrap().is_none());
+        assert_eq!(
+            cache.trunk_hashes(2).unwrap(),
+            vec![chain[1].bitcoin_hash(), chain[2].bitcoin_hash()]
+        );
+        assert_eq!(cache.trunk_hashes(10).unwrap().len(), 4);
+
+        // a longer fork from height 1 replaces heights 2 and 3
+        let fork = mine_chain(&chain[0], 3, 2);
+        cache.add_header(&fork[0]).unwrap();
+        cache.add_header(&fork[1]).unwrap();
+        let (_, unwinds, extensions) = cache.add_header(&fork[2]).unwrap().unwrap();
+        assert_eq!(
+            unwinds.unwrap(),
+            vec![chain[2].bitcoin_hash(), chain[1].bitcoin_hash()]
+        );
+        assert_eq!(
+            extensions.unwrap(),
+            fork.iter().map(|h| h.bitcoin_hash()).collect::<Vec<_>>()
+        );
+
+        assert_eq!(cache.tip().unwrap().unwrap().stored.header, fork[2]);
+        for (i, header) in fork.iter().enumerate() {
+            let stored = cache.get_header_for_height(i as u32 + 2).unwrap().unwrap();
+            assert_eq!(stored.stored.header, *header);
+        }
+        assert!(cache
+            .pos_on_trunk(&chain[2].bitcoin_hash())
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            cache.pos_on_trunk(&chain[0].bitcoin_hash()).unwrap(),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn prune_stale_forks() {
+        let genesis = Network::Regtest.genesis_header();
+        let mut store = MapStore::new();
+        let mut cache = HeaderCache::new(Network::Regtest, &mut store);
+        cache.add_header_raw(genesis, 0).unwrap();
+
+        let chain = mine_chain(&genesis, 10, 1);
+        let fork = mine_chain(&chain[4], 2, 2);
+        for header in chain.iter().chain(fork.iter()) {
+            cache.add_header(header).unwrap();
+        }
+        assert!(cache.get_header(&fork[1].bitcoin_hash()).unwrap().is_some());
+
+        // extend the trunk until the fork is final
+        let extension = mine_chain(&chain[9], FINALITY_DEPTH as usize - 4, 1);
+        for header in extension.iter() {
+            cache.add_header(header).unwrap();
+        }
+        assert!(cache.get_header(&fork[0].bitcoin_hash()).unwrap().is_none());
+        assert!(cache.get_header(&fork[1].bitcoin_hash()).unwrap().is_some());
+        assert!(cache
+            .get_header(&chain[5].bitcoin_hash())
+            .unwrap()
+            .is_some());
+
+        let last = *extension.last().unwrap();
+        cache.add_header(&mine_chain(&last, 1, 1)[0]).unwrap();
+        assert!(cache.get_header(&fork[1].bitcoin_hash()).unwrap().is_none());
+        assert!(cache
+            .get_header(&chain[6].bitcoin_hash())
+            .unwrap()
+            .is_some());
+
+        // pruned headers are unknown
+        let pruned_fork = mine_chain(&fork[1], 1, 2);
+        assert!(cache.add_header(&pruned_fork[0]).is_err());
+
+        // forks from final trunk headers are kept until the tip changes
+        let deep_fork = mine_chain(&chain[5], 1, 3);
+        cache.add_header(&deep_fork[0]).unwrap();
+        let deep_hash = deep_fork[0].bitcoin_hash();
+        assert!(cache.get_header(&deep_hash).unwrap().is_some());
+        let last = cache.tip().unwrap().unwrap().stored.header;
+        cache.add_header(&mine_chain(&last, 1, 1)[0]).unwrap();
+        assert!(cache.get_header(&deep_hash).unwrap().is_none());
+    }
+
+    #[test]
+    fn deep_fork_reconnects_through_pruned_parent() {
+        let genesis = Network::Regtest.genesis_header();
+        let mut store = MapStore::new();
+        let mut cache = HeaderCache::new(Network::Regtest, &mut store);
+        cache.add_header_raw(genesis, 0).unwrap();
+
+        let chain = mine_chain(&genesis, 10, 1);
+        let fork = mine_chain(&chain[4], 2, 2);
+        for header in chain.iter().chain(fork.iter()) {
+            cache.add_header(header).unwrap();
+        }
+
+        // the trunk advances until the fork is pruned
+        let extension = mine_chain(&chain[9], FINALITY_DEPTH as usize - 3, 1);
+        for header in extension.iter() {
+            cache.add_header(header).unwrap();
+        }
+        let tip_height = cache.tip().unwrap().unwrap().stored.height;
+        assert!(cache.get_header(&fork[1].bitcoin_hash()).unwrap().is_none());
+
+        // resending the pruned headers reconnects the fork, which overtakes
+        // the trunk
+        let mut fork = fork;
+        let extension = mine_chain(&fork[1], tip_height as usize - 6, 2);
+        fork.extend(extension);
+        let mut unwinds = vec![];
+        for header in fork.iter() {
+            if let Some((_, Some(unwound), _)) = cache.add_header(header).unwrap() {
+                unwinds.extend(unwound);
+            }
+        }
+        assert_eq!(unwinds.len(), tip_height as usize - 5);
+        let tip = cache.tip().unwrap().unwrap();
+        assert_eq!(tip.stored.header, *fork.last().unwrap());
+        assert_eq!(tip.stored.height, tip_height + 1);
+        assert_eq!(
+            cache.pos_on_trunk(&fork[0].bitcoin_hash()).unwrap(),
+            Some(6)
+        );
+
+        // and the old trunk headers are pruned in turn
+        assert!(cache
+            .get_header(&chain[5].bitcoin_hash())
+            .unwrap()
+            .is_none());
+    }
+
+    fn child(prev: &BlockHeader, time: u32, version: u32) -> BlockHeader {
+        let mut header = BlockHeader {
+            version,
+            prev_blockhash: prev.bitcoin_hash(),
+            time,
+            nonce: 0,
+            ..*prev
+        };
+        mine(&mut header);
+        header
+    }
+
+    fn assert_rejected(cache: &mut HeaderCache, header: &BlockHeader, message: &str) {
+        let err = cache.add_header(header).unwrap_err();
+        assert_eq!(err.to_string(), message);
+    }
+
+    #[test]
+    fn median_time_past() {
+        let start = child(&Network::Regtest.genesis_header(), 1_000, 4);
+        let mut store = MapStore::new();
+        let mut cache = HeaderCache::new(Network::Regtest, &mut store);
+        cache.add_header_raw(start, 1).unwrap();
+
+        // with only one known ancestor, the header must be after it
+        assert_rejected(
+            &mut cache,
+            &child(&start, 1_000, 4),
+            "header time is not after the median time past",
+        );
+
+        // ten more blocks at times 1010, 1020, .., 1100
+        let mut prev = start;
+        for i in 1..=10 {
+            prev = child(&prev, 1_000 + 10 * i, 4);
+            cache.add_header(&prev).unwrap();
+        }
+
+        // the median of the last 11 blocks is 1050
+        assert_rejected(
+            &mut cache,
+            &child(&prev, 1_050, 4),
+            "header time is not after the median time past",
+        );
+        // but the time may go back past the previous block
+        cache.add_header(&child(&prev, 1_051, 4)).unwrap();
+    }
+
+    #[test]
+    fn future_time_limit() {
+        let start = child(&Network::Regtest.genesis_header(), 1_000, 4);
+        let mut store = MapStore::new();
+        let mut cache = HeaderCache::new(Network::Regtest, &mut store);
+        cache.add_header_raw(start, 1).unwrap();
+
+        // not checked until the current time is known
+        cache.add_header(&child(&start, 1_000_000, 4)).unwrap();
+
+        cache.set_time(10_000);
+        assert_rejected(
+            &mut cache,
+            &child(&start, 10_000 + 2 * 60 * 60 + 1, 4),
+            "header time is too far in the future",
+        );
+        cache
+            .add_header(&child(&start, 10_000 + 2 * 60 * 60, 4))
+            .unwrap();
+    }
+
+    #[test]
+    fn minimum_versions() {
+        let start = child(&Network::Regtest.genesis_header(), 1_000, 1);
+
+        // BIP34, BIP66 and BIP65 are active from the start on regtest
+        let mut store = MapStore::new();
+        let mut cache = HeaderCache::new(Network::Regtest, &mut store);
+        cache.add_header_raw(start, 1).unwrap();
+        assert_rejected(
+            &mut cache,
+            &child(&start, 1_001, 3),
+            "obsolete header version",
+        );
+        // negative in Bitcoin Core
+        assert_rejected(
+            &mut cache,
+            &child(&start, 1_001, 0x8000_0004),
+            "obsolete header version",
+        );
+        cache.add_header(&child(&start, 1_001, 4)).unwrap();
+
+        // on testnet, the minimum depends on the height
+        for (height, version) in [(21_110, 2), (330_775, 3), (581_884, 4)].iter() {
+            let mut store = MapStore::new();
+            let mut cache = HeaderCache::new(Network::Testnet, &mut store);
+            cache.add_header_raw(start, *height).unwrap();
+            assert_rejected(
+                &mut cache,
+                &child(&start, 1_001, version - 1),
+                "obsolete header version",
+            );
+            cache.add_header(&child(&start, 1_001, *version)).unwrap();
+        }
+    }
 
     #[test]
     fn test_bytes_to_hashes() {
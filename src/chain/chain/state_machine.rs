@@ -1,8 +1,12 @@
-use super::{accounts, peg, work, Action};
-use crate::core::primitives::transaction::Transaction;
+use super::genesis::Genesis;
+use super::governance::{self, ProposalStatus};
+use super::{accounts, params, peg, work, Action, Event};
+use crate::core::primitives::transaction::{Proposal, Transaction};
 use crate::core::primitives::Result;
+use log::{info, warn};
+use orga::abci::messages::Header;
 use orga::Store;
-use orga::{state, WrapStore};
+use orga::{state, Value, WrapStore};
 use std::collections::BTreeMap;
 
 #[state]
@@ -10,14 +14,21 @@ pub struct State {
     pub peg: peg::State,
     pub accounts: accounts::State,
     pub work: work::State,
+    pub params: params::State,
+    pub governance: governance::State,
+    /// Time of the current block, from its Tendermint header.
+    pub block_time: Value<u64>,
 }
 
+/// Runs an action against the state, returning the events it emitted.
 pub fn run<S: Store>(
     store: S,
     action: Action,
     validators: &mut BTreeMap<Vec<u8>, u64>,
-) -> Result<()> {
+) -> Result<Vec<Event>> {
     let mut state = State::wrap_store(store)?;
+    let params = state.params.get_or_default()?;
+    let mut events = vec![];
 
     #[cfg_attr(rustfmt, rustfmt_skip)]
     match action {
@@ -30,25 +41,240 @@ pub fn run<S: Store>(
             Transaction::Signature(tx) =>
                 peg::handlers::signature_tx(&mut state.peg, tx),
             Transaction::Header(tx) =>
-                peg::handlers::header_tx(&mut state.peg, tx),
+                peg::handlers::header_tx(&mut state.peg, &mut state.accounts, tx)
+                    .map(|header_events| events = header_events),
+            Transaction::Pause(tx) =>
+                peg::handlers::pause_tx(&mut state.peg, validators, tx),
 
             // Account transactions
             Transaction::Transfer(tx) =>
-                accounts::handlers::transfer_tx(&mut state.accounts, tx),
+                accounts::handlers::transfer_tx(&mut state.accounts, &params, tx),
 
             // Validator transactions
             Transaction::WorkProof(tx) =>
-                work::handlers::work_proof_tx(&mut state.work, validators, tx),
+                work::handlers::work_proof_tx(&mut state.work, validators, &params, tx),
+            Transaction::Governance(tx) => {
+                let now = state.block_time.get_or_default()?;
+                governance::handlers::governance_tx(&mut state.governance, validators, now, tx)
+            }
         },
-        Action::BeginBlock(header) => {
-            peg::handlers::begin_block(&mut state.peg, validators, header)
+        Action::InitChain(genesis) => init_chain(&mut state, genesis),
+        Action::BeginBlock(header) => begin_block(&mut state, validators, header),
+    }?;
+    Ok(events)
+}
+
+/// Applies the governance proposals which have passed, then runs the peg's
+/// block logic so paused or rotated state takes effect in the same block.
+fn begin_block<S: Store>(
+    state: &mut State<S>,
+    validators: &BTreeMap<Vec<u8>, u64>,
+    header: Header,
+) -> Result<()> {
+    let now = header.get_time().get_seconds() as u64;
+    state.block_time.set(now)?;
+    let passed = governance::handlers::begin_block(&mut state.governance, validators, now)?;
+    for (id, proposal) in passed {
+        // a proposal which can't be applied must not halt the chain
+        let status = match execute_proposal(state, validators, now, proposal) {
+            Ok(()) => {
+                info!("executed governance proposal {}", id);
+                ProposalStatus::Executed
+            }
+            Err(err) => {
+                warn!("failed to execute governance proposal {}: {}", id, err);
+                ProposalStatus::Failed
+            }
+        };
+        state.governance.close(id, status)?;
+    }
+
+    peg::handlers::begin_block(&mut state.peg, validators, header)
+}
+
+fn execute_proposal<S: Store>(
+    state: &mut State<S>,
+    validators: &BTreeMap<Vec<u8>, u64>,
+    now: u64,
+    proposal: Proposal,
+) -> Result<()> {
+    match proposal {
+        Proposal::SetParams(params) => {
+            params.validate()?;
+            state.params.set(params)
         }
+        Proposal::SetPegParams(params) => {
+            params.validate()?;
+            state.peg.params.set(params)
+        }
+        Proposal::RotateSignatories => {
+            peg::handlers::rotate_signatories(&mut state.peg, validators, now)
+        }
+        Proposal::SetPegPaused(paused) => state.peg.set_paused(paused),
     }
 }
 
-// TODO: this should be Action::InitChain
-/// Called once at genesis to write some data to the store.
-pub fn initialize<S: Store>(store: S) -> Result<()> {
-    let mut state = State::wrap_store(store)?;
-    peg::handlers::initialize(&mut state.peg)
+/// Called once at genesis to write the initial state.
+fn init_chain<S: Store>(state: &mut State<S>, genesis: Genesis) -> Result<()> {
+    genesis.params.validate()?;
+    state.params.set(genesis.params.clone())?;
+    for (address, balance) in genesis.account_balances()? {
+        state.accounts.mint(address, balance)?;
+    }
+    peg::handlers::initialize(&mut state.peg, &genesis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::genesis::GenesisAccount;
+    use super::super::test_utils::{create_keypair, mock_validator_set, sign};
+    use super::*;
+    use crate::core::bitcoin::{bitcoin, Network};
+    use crate::core::primitives::transaction::{
+        GovernanceTransaction, PauseTransaction, TransferTransaction,
+    };
+    use orga::MapStore;
+    use protobuf::well_known_types::Timestamp;
+
+    fn header(time: i64) -> Header {
+        let mut header: Header = Default::default();
+        let mut timestamp = Timestamp::new();
+        timestamp.set_seconds(time);
+        header.set_time(timestamp);
+        header
+    }
+
+    /// Initializes a chain with one validator which submits `proposals`, then
+    /// runs a block so the proposals are executed.
+    fn run_proposals(proposals: Vec<Proposal>) -> MapStore {
+        let mut store = MapStore::new();
+        let (mut validators, privkeys) = mock_validator_set();
+        let genesis = Genesis {
+            network: Network::Regtest,
+            ..Default::default()
+        };
+        run(&mut store, Action::InitChain(genesis), &mut validators).unwrap();
+        run(&mut store, Action::BeginBlock(header(1)), &mut validators).unwrap();
+
+        for (id, proposal) in proposals.into_iter().enumerate() {
+            let mut tx = GovernanceTransaction {
+                validator: validators.keys().next().unwrap().clone(),
+                proposal_id: id as u64,
+                proposal: Some(proposal),
+                signature: vec![],
+            };
+            tx.signature = sign(&mut tx, privkeys[0]);
+            let action = Action::Transaction(Transaction::Governance(tx));
+            run(&mut store, action, &mut validators).unwrap();
+        }
+        run(&mut store, Action::BeginBlock(header(2)), &mut validators).unwrap();
+        store
+    }
+
+    #[test]
+    fn governance_pauses_peg() {
+        let mut store = run_proposals(vec![Proposal::SetPegPaused(true)]);
+
+        let state = State::wrap_store(&mut store).unwrap();
+        assert!(state.peg.is_paused().unwrap());
+        let record = state.governance.get(0).unwrap().unwrap();
+        assert_eq!(record.status, ProposalStatus::Executed);
+        assert_eq!(record.start_time, 1);
+        assert!(state.governance.open_proposals().unwrap().is_empty());
+    }
+
+    #[test]
+    fn governance_sets_params() {
+        let params = params::Params {
+            min_transfer_fee: 1,
+            ..Default::default()
+        };
+        let invalid_peg_params = peg::Params {
+            signatory_change_interval: 0,
+            ..Default::default()
+        };
+        let mut store = run_proposals(vec![
+            Proposal::SetParams(params.clone()),
+            Proposal::SetPegParams(invalid_peg_params),
+        ]);
+
+        let state = State::wrap_store(&mut store).unwrap();
+        assert_eq!(state.params.get().unwrap(), params);
+        assert_eq!(state.peg.params().unwrap(), peg::Params::default());
+        let record = state.governance.get(1).unwrap().unwrap();
+        assert_eq!(record.status, ProposalStatus::Failed);
+    }
+
+    #[test]
+    fn governance_sets_checkpoint_fee_rate() {
+        let default_fee_rate = peg::Params::default().checkpoint_fee_rate;
+        let peg_params = peg::Params {
+            checkpoint_fee_rate: default_fee_rate * 2,
+            ..Default::default()
+        };
+        let mut store = run_proposals(vec![Proposal::SetPegParams(peg_params)]);
+        let mut default_store = run_proposals(vec![]);
+
+        let state = State::wrap_store(&mut store).unwrap();
+        let default_state = State::wrap_store(&mut default_store).unwrap();
+        assert_eq!(state.peg.fee_rate().unwrap(), default_fee_rate * 2);
+
+        let script = bitcoin::Script::from(vec![0; 22]);
+        assert_eq!(
+            state.peg.withdrawal_fee(&script).unwrap(),
+            default_state.peg.withdrawal_fee(&script).unwrap() * 2
+        );
+    }
+
+    #[test]
+    fn transfers_while_peg_paused() {
+        let mut store = MapStore::new();
+        let (mut validators, privkeys) = mock_validator_set();
+        let (sender_privkey, sender_pubkey) = create_keypair(5);
+        let genesis = Genesis {
+            network: Network::Regtest,
+            accounts: vec![GenesisAccount {
+                address: hex::encode(&sender_pubkey.serialize()[..]),
+                balance: 10_000,
+            }],
+            ..Default::default()
+        };
+        run(&mut store, Action::InitChain(genesis), &mut validators).unwrap();
+        run(&mut store, Action::BeginBlock(header(1)), &mut validators).unwrap();
+
+        let mut tx = PauseTransaction {
+            validator: validators.keys().next().unwrap().clone(),
+            paused: true,
+            round: 0,
+            signature: vec![],
+        };
+        tx.signature = sign(&mut tx, privkeys[0]);
+        run(
+            &mut store,
+            Action::Transaction(Transaction::Pause(tx)),
+            &mut validators,
+        )
+        .unwrap();
+
+        let mut tx = TransferTransaction {
+            from: sender_pubkey.serialize().to_vec(),
+            to: vec![124; 33],
+            amount: 100,
+            signature: vec![],
+            nonce: 0,
+            fee_amount: params::MIN_TRANSFER_FEE,
+        };
+        tx.signature = sign(&mut tx, sender_privkey);
+        run(
+            &mut store,
+            Action::Transaction(Transaction::Transfer(tx)),
+            &mut validators,
+        )
+        .unwrap();
+
+        let state = State::wrap_store(&mut store).unwrap();
+        assert!(state.peg.is_paused().unwrap());
+        let receiver = state.accounts.get([124; 33]).unwrap().unwrap();
+        assert_eq!(receiver.balance, 100);
+    }
 }
@@ -1,6 +1,6 @@
 use super::peg::handlers::signatories_from_validators;
 use super::spv::headercache::HeaderCache;
-use crate::core::bitcoin::bitcoin;
+use crate::core::bitcoin::{bitcoin, Network};
 use crate::core::primitives::{
     transaction::{DepositTransaction, Sighash},
     Account, Address,
@@ -8,6 +8,7 @@ use crate::core::primitives::{
 use bitcoin::consensus::encode as bitcoin_encode;
 use bitcoin::util::hash::bitcoin_merkle_root;
 use bitcoin::util::merkleblock::PartialMerkleTree;
+use bitcoin::BitcoinHash;
 use lazy_static::lazy_static;
 use orga::{abci::messages::Header as TendermintHeader, MapStore, Store, WrapStore};
 
@@ -43,6 +44,12 @@ impl MockNet {
     }
 
     pub fn with_btc_block(initial_block: bitcoin::Block) -> Self {
+        MockNet::with_confirmations(initial_block, super::peg::DEPOSIT_CONFIRMATION_DEPTH)
+    }
+
+    /// Creates a mock net whose SPV chain starts at `initial_block`, with
+    /// `confirmations` more headers mined on top of it.
+    pub fn with_confirmations(initial_block: bitcoin::Block, confirmations: u32) -> Self {
         let validators = mock_validator_set();
         let mut net = MockNet {
             store: Default::default(),
@@ -53,9 +60,16 @@ impl MockNet {
         };
 
         let mut state = super::peg::State::wrap_store(&mut net.store).unwrap();
-        HeaderCache::new(bitcoin::Network::Regtest, &mut state.headers)
+        state.network.set(Network::Regtest).unwrap();
+        let mut header_cache = HeaderCache::new(Network::Regtest, &mut state.headers);
+        header_cache
             .add_header_raw(initial_block.header, 0)
             .expect("failed to create mock net");
+        for header in mine_headers(&initial_block.header, confirmations as usize) {
+            header_cache
+                .add_header(&header)
+                .expect("failed to create mock net");
+        }
 
         // initial beginblock
         let mut header: TendermintHeader = Default::default();
@@ -74,13 +88,17 @@ impl MockNet {
         bitcoin::Transaction,
         bitcoin::util::merkleblock::PartialMerkleTree,
     ) {
-        let tx = self.btc_block.txdata[0].clone();
-        let mut txids = HashSet::new();
-        txids.insert(tx.txid());
-        (
-            tx,
-            bitcoin::MerkleBlock::from_block(&self.btc_block, &txids).txn,
-        )
+        self.create_btc_proof_at(0)
+    }
+
+    pub fn create_btc_proof_at(
+        &self,
+        index: usize,
+    ) -> (
+        bitcoin::Transaction,
+        bitcoin::util::merkleblock::PartialMerkleTree,
+    ) {
+        create_btc_proof_for(&self.btc_block, index)
     }
 
     pub fn with_active_checkpoint() -> MockNet {
@@ -155,6 +173,77 @@ pub fn build_block(txs: Vec<bitcoin::Transaction>) -> bitcoin::Block {
     }
 }
 
+pub fn create_btc_proof_for(
+    block: &bitcoin::Block,
+    index: usize,
+) -> (
+    bitcoin::Transaction,
+    bitcoin::util::merkleblock::PartialMerkleTree,
+) {
+    let tx = block.txdata[index].clone();
+    let mut txids = HashSet::new();
+    txids.insert(tx.txid());
+    (tx, bitcoin::MerkleBlock::from_block(block, &txids).txn)
+}
+
+/// Mines `count` headers with trivial proof of work on top of `prev`.
+pub fn mine_headers(prev: &bitcoin::BlockHeader, count: usize) -> Vec<bitcoin::BlockHeader> {
+    let mut prev = *prev;
+    let mut headers = Vec::with_capacity(count);
+    for _ in 0..count {
+        let mut header = bitcoin::BlockHeader {
+            version: 0x2000_0000,
+            prev_blockhash: prev.bitcoin_hash(),
+            merkle_root: Default::default(),
+            time: prev.time + 1,
+            bits: prev.bits,
+            nonce: 0,
+        };
+        mine(&mut header);
+        headers.push(header);
+        prev = header;
+    }
+    headers
+}
+
+/// Mines a block containing `txs` on top of `prev`.
+pub fn mine_block(prev: &bitcoin::BlockHeader, txs: Vec<bitcoin::Transaction>) -> bitcoin::Block {
+    let mut block = build_block(txs);
+    block.header.version = 0x2000_0000;
+    block.header.prev_blockhash = prev.bitcoin_hash();
+    block.header.time = prev.time + 1;
+    block.header.bits = prev.bits;
+    mine(&mut block.header);
+    block
+}
+
+fn mine(header: &mut bitcoin::BlockHeader) {
+    while header.validate_pow(&header.target()).is_err() {
+        header.nonce += 1;
+    }
+}
+
+pub fn p2pkh_script() -> bitcoin::Script {
+    [vec![0x76, 0xa9, 0x14], vec![123; 20], vec![0x88, 0xac]]
+        .concat()
+        .into()
+}
+
+pub fn p2sh_script() -> bitcoin::Script {
+    [vec![0xa9, 0x14], vec![123; 20], vec![0x87]]
+        .concat()
+        .into()
+}
+
+/// Returns a P2WPKH output script, e.g. for use as a withdrawal destination.
+pub fn p2wpkh_script() -> bitcoin::Script {
+    [vec![0x00, 0x14], vec![123; 20]].concat().into()
+}
+
+pub fn p2wsh_script() -> bitcoin::Script {
+    [vec![0x00, 0x20], vec![123; 32]].concat().into()
+}
+
 pub fn invalidate_proof(proof: PartialMerkleTree) -> PartialMerkleTree {
     let mut proof_bytes = bitcoin_encode::serialize(&proof);
     proof_bytes[10] ^= 1;
@@ -192,11 +281,10 @@ pub fn create_sender<S: Store>(
     let (privkey, pubkey) = create_keypair(1);
     let address = pubkey.serialize().to_vec();
 
+    let account_address = unsafe_slice_to_address(address.as_slice());
+    accounts.mint(account_address, balance).unwrap();
     accounts
-        .insert(
-            unsafe_slice_to_address(address.as_slice()),
-            Account { balance, nonce },
-        )
+        .insert(account_address, Account { balance, nonce })
         .unwrap();
 
     Sender { address, privkey }
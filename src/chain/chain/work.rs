@@ -1,3 +1,4 @@
+use super::params::Params;
 use crate::core::primitives::{transaction::WorkProofTransaction, Result};
 use crate::core::work::work;
 use failure::bail;
@@ -5,8 +6,6 @@ use orga::{collections::Set, Store};
 use sha2::{Digest, Sha256};
 use std::collections::BTreeMap;
 
-const MIN_WORK: u64 = 1 << 20;
-
 pub type State<S> = Set<S, [u8; 32]>;
 
 pub mod handlers {
@@ -15,6 +14,7 @@ pub mod handlers {
     pub fn work_proof_tx<S: Store>(
         redeemed_work_hashes: &mut State<S>,
         validators: &mut BTreeMap<Vec<u8>, u64>,
+        params: &Params,
         tx: WorkProofTransaction,
     ) -> Result<()> {
         let mut hasher = Sha256::new();
@@ -24,7 +24,7 @@ pub mod handlers {
         let hash: [u8; 32] = hasher.result().into();
         let work_proof_value = work(&hash);
 
-        if work_proof_value < MIN_WORK {
+        if work_proof_value < params.min_work {
             bail!("Proof has less than minimum work value")
         }
 
@@ -35,7 +35,7 @@ pub mod handlers {
 
         // Grant voting power
         let current_voting_power = *validators.get(&tx.public_key).unwrap_or(&0);
-        let new_voting_power = work_proof_value / MIN_WORK;
+        let new_voting_power = work_proof_value / params.min_work;
 
         validators.insert(tx.public_key, current_voting_power + new_voting_power);
         // Write the redeemed hash to the store so it can't be replayed
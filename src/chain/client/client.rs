@@ -1,12 +1,14 @@
 // use self::Result;
+use crate::chain::chain::governance::ProposalRecord;
+use crate::chain::chain::params::Params;
+use crate::chain::chain::peg::{self, Checkpoint, ReorgedDeposit, ReserveReport};
 use crate::chain::chain::{orga, spv, State};
-use crate::core::bitcoin::bitcoin;
+use crate::core::bitcoin::{bitcoin, Network};
 use crate::core::primitives::transaction::{Transaction, WorkProofTransaction};
 use crate::core::primitives::Account;
 use crate::core::signatory_set::{SignatorySet, SignatorySetSnapshot};
 use crate::Result;
 use bitcoin::hash_types::BlockHash as Hash;
-use bitcoin::Network::Testnet as bitcoin_network;
 use blocking::block_on;
 use failure::bail;
 use orga::{
@@ -15,7 +17,7 @@ use orga::{
 };
 
 use std::cell::{RefCell, RefMut};
-use std::ops::DerefMut;
+use std::ops::{DerefMut, Range};
 use std::str::FromStr;
 use tendermint_rpc::Client as TendermintRpcClient;
 
@@ -104,14 +106,16 @@ impl Client {
 
     /// Get the Bitcoin headers currently used by the peg zone's on-chain SPV client.
     pub fn get_bitcoin_block_hashes(&self) -> Result<Vec<Hash>> {
+        let network = self.get_network()?;
         let state = &mut self.state()?.peg.headers;
-        let mut header_cache = spv::headercache::HeaderCache::new(bitcoin_network, state);
-        let trunk = header_cache.load_trunk();
+        let mut header_cache = spv::headercache::HeaderCache::new(network, state);
+        // enough for the relayer to find a common ancestor with bitcoind
+        let trunk = header_cache.trunk_hashes(2016)?;
 
-        match trunk {
-            Some(trunk) => Ok(trunk.clone()),
-            None => bail!("Unable to get header trunk"),
+        if trunk.is_empty() {
+            bail!("Unable to get header trunk");
         }
+        Ok(trunk)
     }
 
     /// Create and broadcast a transaction which reedems a golden nonce, granting voting power to
@@ -129,9 +133,9 @@ impl Client {
     }
 
     pub fn get_bitcoin_tip(&self) -> OrgaResult<bitcoin::BlockHeader> {
+        let network = self.get_network()?;
         let mut store = self.store.borrow_mut();
-        let mut header_cache =
-            spv::headercache::HeaderCache::new(bitcoin_network, store.deref_mut());
+        let mut header_cache = spv::headercache::HeaderCache::new(network, store.deref_mut());
         let maybe_tip = header_cache.tip()?;
         if let Some(tip) = maybe_tip {
             Ok(tip.stored.header)
@@ -140,6 +144,21 @@ impl Client {
         }
     }
 
+    /// Get the Bitcoin network the peg operates on.
+    pub fn get_network(&self) -> OrgaResult<Network> {
+        self.state()?.peg.network()
+    }
+
+    /// Get the parameters of the account and work handlers.
+    pub fn get_params(&self) -> OrgaResult<Params> {
+        self.state()?.params.get_or_default()
+    }
+
+    /// Get the peg parameters.
+    pub fn get_peg_params(&self) -> OrgaResult<peg::Params> {
+        self.state()?.peg.params()
+    }
+
     pub fn get_signatory_sets(&self) -> OrgaResult<Vec<SignatorySet>> {
         self.state()?
             .peg
@@ -149,10 +168,58 @@ impl Client {
             .collect()
     }
 
+    pub fn get_signatory_set_snapshots(&self) -> OrgaResult<Vec<SignatorySetSnapshot>> {
+        self.state()?.peg.signatory_sets.iter().collect()
+    }
+
     pub fn get_signatory_set_snapshot(&self) -> OrgaResult<SignatorySetSnapshot> {
         self.state()?.peg.current_signatory_set()
     }
 
+    /// Get the credited deposits whose Bitcoin block was removed from the SPV
+    /// trunk by a reorg after their coins were minted.
+    pub fn get_reorged_deposits(&self) -> OrgaResult<Vec<ReorgedDeposit>> {
+        self.state()?.peg.reorged_deposits.iter().collect()
+    }
+
+    /// Get the record of the checkpoint with the given index, if it exists.
+    pub fn get_checkpoint(&self, index: u64) -> OrgaResult<Option<Checkpoint>> {
+        self.state()?.peg.checkpoints.get(index)
+    }
+
+    /// Get the records of the checkpoints with indexes in the given range,
+    /// along with their indexes. Checkpoint indexes start at 1.
+    pub fn list_checkpoints(&self, range: Range<u64>) -> OrgaResult<Vec<(u64, Checkpoint)>> {
+        let state = self.state()?;
+        let mut checkpoints = vec![];
+        for index in range {
+            if let Some(checkpoint) = state.peg.checkpoints.get(index)? {
+                checkpoints.push((index, checkpoint));
+            }
+        }
+        Ok(checkpoints)
+    }
+
+    /// Get the governance proposal with the given id, if it exists.
+    pub fn get_proposal(&self, id: u64) -> OrgaResult<Option<ProposalRecord>> {
+        self.state()?.governance.get(id)
+    }
+
+    /// Get the governance proposals which are still open, along with their ids.
+    pub fn get_open_proposals(&self) -> OrgaResult<Vec<(u64, ProposalRecord)>> {
+        self.state()?.governance.open_proposals()
+    }
+
+    /// Returns true if the peg has been paused.
+    pub fn is_peg_paused(&self) -> OrgaResult<bool> {
+        self.state()?.peg.is_paused()
+    }
+
+    /// Get the peg's pause round, which pause signals must be made for.
+    pub fn get_pause_round(&self) -> OrgaResult<u64> {
+        self.state()?.peg.pause_round.get_or_default()
+    }
+
     pub fn get_balance(&self, address: &[u8]) -> OrgaResult<u64> {
         let account = self.get_account(address)?;
         Ok(account.balance)
@@ -183,6 +250,13 @@ impl Client {
             Ok(None)
         }
     }
+
+    /// Compare the total NBTC supply with the Bitcoin reserve backing it.
+    pub fn reserve_report(&self) -> OrgaResult<ReserveReport> {
+        let state = self.state()?;
+        let supply = state.accounts.supply()?;
+        state.peg.reserve_report(supply)
+    }
 }
 
 type Address = [u8; 33];
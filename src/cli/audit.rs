@@ -0,0 +1,97 @@
+use crate::core::bitcoin::bitcoin;
+use crate::core::signatory_set::{parse_redeem_script, ParsedRedeemScript, SignatorySetSnapshot};
+
+/// An output of an audited transaction which pays to a reserve script of a
+/// known signatory set.
+#[derive(Debug)]
+pub struct ReserveOutput {
+    pub index: usize,
+    pub value: u64,
+    pub signatory_set_index: u64,
+    /// Whether the output pays to the current signatory set.
+    pub current: bool,
+    /// The depositor data commitment, empty for checkpoint change outputs.
+    pub data: Vec<u8>,
+}
+
+/// An input of an audited transaction which spends a reserve output, decoded
+/// from the witness script.
+#[derive(Debug)]
+pub struct ReserveInput {
+    pub index: usize,
+    pub script: ParsedRedeemScript,
+    /// The index of the signatory set which builds exactly this script, if
+    /// any.
+    pub signatory_set_index: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct Audit {
+    pub outputs: Vec<ReserveOutput>,
+    pub inputs: Vec<ReserveInput>,
+}
+
+/// Checks which outputs of `tx` pay to the reserve of one of the given
+/// signatory sets (ordered by index, the last being the current set) and
+/// decodes the reserve scripts spent by its inputs.
+///
+/// Reserve outputs only commit to a hash of their script, so outputs are
+/// matched by rebuilding the script of every set for each of `recipients`
+/// (deposit data) and for empty data (checkpoint change).
+pub fn audit_tx(
+    tx: &bitcoin::Transaction,
+    signatory_sets: &[SignatorySetSnapshot],
+    recipients: &[Vec<u8>],
+) -> Audit {
+    let mut candidates = vec![];
+    for (i, snapshot) in signatory_sets.iter().enumerate() {
+        let mut data = vec![vec![]];
+        data.extend(recipients.iter().cloned());
+        for data in data {
+            let script = snapshot.output_script(data.clone());
+            candidates.push((i as u64, script, data));
+        }
+    }
+
+    let outputs = tx
+        .output
+        .iter()
+        .enumerate()
+        .filter_map(|(index, txout)| {
+            candidates
+                .iter()
+                .find(|(_, script, _)| *script == txout.script_pubkey)
+                .map(|(signatory_set_index, _, data)| ReserveOutput {
+                    index,
+                    value: txout.value,
+                    signatory_set_index: *signatory_set_index,
+                    current: *signatory_set_index + 1 == signatory_sets.len() as u64,
+                    data: data.clone(),
+                })
+        })
+        .collect();
+
+    let inputs = tx
+        .input
+        .iter()
+        .enumerate()
+        .filter_map(|(index, txin)| {
+            let script_bytes = txin.witness.last()?;
+            let script = parse_redeem_script(script_bytes.as_slice()).ok()?;
+            let signatory_set_index = signatory_sets
+                .iter()
+                .position(|snapshot| {
+                    snapshot.redeem_script(script.data.clone()).as_bytes()
+                        == script_bytes.as_slice()
+                })
+                .map(|i| i as u64);
+            Some(ReserveInput {
+                index,
+                script,
+                signatory_set_index,
+            })
+        })
+        .collect();
+
+    Audit { outputs, inputs }
+}
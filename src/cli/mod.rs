@@ -1,3 +1,4 @@
 // pub mod cli;
+pub mod audit;
 pub mod tendermint;
 pub mod wallet;
@@ -1,3 +1,5 @@
+use crate::chain::chain::genesis::Genesis;
+use crate::core::bitcoin::Network;
 use hex_literal::hex;
 use is_executable::IsExecutable;
 use log::{debug, info};
@@ -72,7 +74,7 @@ pub fn install(nomic_home: &PathBuf) {
         .expect("Failed to set Tendermint binary permissions");
 }
 
-pub fn init(nomic_home: &PathBuf, dev_mode: bool) {
+pub fn init(nomic_home: &PathBuf, dev_mode: bool, network: Network) {
     let tendermint_path = nomic_home.join("tendermint-v0.32.8");
 
     // Initialize Tendermint for testnet
@@ -114,8 +116,21 @@ pub fn init(nomic_home: &PathBuf, dev_mode: bool) {
             index..(index + pattern.len()),
             "\"pub_key_types\": [\"secp256k1\"]",
         );
-        genesis
+
+        let mut genesis: serde_json::Value =
+            serde_json::from_str(genesis.as_str()).expect("Failed to parse genesis");
+        // write out every parameter so they can be edited before starting
+        let app_state = Genesis {
+            network,
+            ..Default::default()
+        };
+        genesis["app_state"] =
+            serde_json::to_value(&app_state).expect("Failed to serialize app state");
+        serde_json::to_string_pretty(&genesis).expect("Failed to serialize genesis")
     } else {
+        if network != Network::Testnet {
+            panic!("Only a development chain (--dev) can use a network other than testnet");
+        }
         include_str!("../config/genesis.json").to_string()
     };
     debug!("genesis.json: {}", genesis_str);
@@ -1,8 +1,9 @@
+use crate::chain::chain::peg::is_standard_withdrawal_script;
 use crate::chain::client::Client;
-use crate::core::bitcoin::bitcoin;
+use crate::core::bitcoin::{bitcoin, Network};
 use crate::core::primitives::transaction::Sighash;
 use crate::core::primitives::Result;
-use crate::core::signatory_set::SignatorySet;
+use crate::core::signatory_set::SignatorySetSnapshot;
 use bech32::{FromBase32, ToBase32};
 use failure::bail;
 use log::info;
@@ -12,6 +13,20 @@ use std::path::Path;
 
 const ADDRESS_PREFIX: &str = "nomic";
 
+/// Encodes a sidechain account address (a public key) as a bech32 string.
+pub fn encode_address(pubkey_bytes: &[u8]) -> String {
+    bech32::encode(ADDRESS_PREFIX, pubkey_bytes.to_base32()).unwrap()
+}
+
+/// Decodes a bech32 sidechain account address into its public key bytes.
+pub fn decode_address(address: &str) -> Result<Vec<u8>> {
+    let (prefix, address_u5) = bech32::decode(address)?;
+    if prefix != ADDRESS_PREFIX {
+        bail!("Invalid address prefix");
+    }
+    Ok(Vec::from_base32(&address_u5)?)
+}
+
 pub struct Wallet {
     privkey: secp256k1::SecretKey,
     secp: Secp256k1<SignOnly>,
@@ -46,9 +61,13 @@ impl Wallet {
         }
     }
 
-    pub fn deposit_address(&self, signatories: &SignatorySet) -> bitcoin::Address {
-        let script = crate::core::signatory_set::redeem_script(signatories, self.pubkey_bytes());
-        bitcoin::Address::p2wsh(&script, bitcoin::Network::Testnet)
+    pub fn deposit_address(
+        &self,
+        signatories: &SignatorySetSnapshot,
+        network: Network,
+    ) -> bitcoin::Address {
+        let script = signatories.redeem_script(self.pubkey_bytes());
+        bitcoin::Address::p2wsh(&script, network.address_network())
     }
 
     pub fn pubkey_bytes(&self) -> Vec<u8> {
@@ -56,7 +75,7 @@ impl Wallet {
     }
 
     pub fn receive_address(&self) -> String {
-        bech32::encode(ADDRESS_PREFIX, self.pubkey_bytes().to_base32()).unwrap()
+        encode_address(self.pubkey_bytes().as_slice())
     }
 
     pub fn send(&self, client: &mut Client, address: &str, amount: u64) -> Result<()> {
@@ -64,11 +83,7 @@ impl Wallet {
 
         let sender_address = self.pubkey_bytes();
 
-        let (prefix, receiver_address_u5) = bech32::decode(address)?;
-        if prefix != ADDRESS_PREFIX {
-            bail!("Invalid address prefix");
-        }
-        let receiver_address = Vec::from_base32(&receiver_address_u5)?;
+        let receiver_address = decode_address(address)?;
 
         let account = client.get_account(sender_address.as_slice())?;
         let mut tx = TransferTransaction {
@@ -91,8 +106,19 @@ impl Wallet {
     pub fn withdraw(&self, client: &mut Client, bitcoin_address: &str, amount: u64) -> Result<()> {
         use crate::core::primitives::transaction::{Transaction, WithdrawalTransaction};
 
+        let network = client.get_network()?;
         let address: bitcoin::Address = bitcoin_address.parse()?;
+        if !network.accepts_address(&address) {
+            bail!(
+                "Withdrawal address is for {}, expected {}",
+                address.network,
+                network
+            );
+        }
         let script = address.script_pubkey();
+        if !is_standard_withdrawal_script(&script) {
+            bail!("Withdrawal address must be P2PKH, P2SH, P2WPKH or P2WSH");
+        }
 
         let sender_address = self.pubkey_bytes();
         let account = client.get_account(sender_address.as_slice())?;
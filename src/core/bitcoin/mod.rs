@@ -1,21 +1,203 @@
 pub use bitcoin;
 pub use bitcoincore_rpc;
 
+use std::fmt;
 use std::io::{Read, Write};
+use std::str::FromStr;
 
 use bitcoin::{
     hashes::{sha256d::Hash as Sha2Hash, Hash},
+    util::uint::Uint256,
     BlockHeader,
 };
+use failure::bail;
 use orga::{Decode, Encode, Result};
 use serde::{Deserialize, Serialize};
 
+/// The Bitcoin network the peg operates on, fixed at genesis.
+///
+/// This is separate from `bitcoin::Network` since the version of rust-bitcoin
+/// we use does not know about signet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Network {
+    Bitcoin,
+    Testnet,
+    Regtest,
+    Signet,
+}
+
+impl Network {
+    /// The network used for address encoding. Signet shares testnet's address
+    /// prefixes.
+    pub fn address_network(self) -> bitcoin::Network {
+        match self {
+            Network::Bitcoin => bitcoin::Network::Bitcoin,
+            Network::Testnet | Network::Signet => bitcoin::Network::Testnet,
+            Network::Regtest => bitcoin::Network::Regtest,
+        }
+    }
+
+    /// Returns true if `address` is valid on this network. Base58 addresses
+    /// share their prefixes between testnet, regtest and signet, and
+    /// rust-bitcoin parses them all as testnet, so only segwit addresses,
+    /// whose human-readable part differs, can be told apart.
+    pub fn accepts_address(self, address: &bitcoin::Address) -> bool {
+        use bitcoin::util::address::Payload;
+
+        match address.payload {
+            Payload::WitnessProgram { .. } => address.network == self.address_network(),
+            _ => match self {
+                Network::Bitcoin => address.network == bitcoin::Network::Bitcoin,
+                _ => address.network != bitcoin::Network::Bitcoin,
+            },
+        }
+    }
+
+    /// The default RPC port of a Bitcoin Core node on this network.
+    pub fn rpc_port(self) -> u16 {
+        match self {
+            Network::Bitcoin => 8332,
+            Network::Testnet => 18332,
+            Network::Regtest => 18443,
+            Network::Signet => 38332,
+        }
+    }
+
+    /// The header of the network's genesis block.
+    pub fn genesis_header(self) -> BlockHeader {
+        match self {
+            Network::Bitcoin => {
+                bitcoin::blockdata::constants::genesis_block(bitcoin::Network::Bitcoin).header
+            }
+            Network::Testnet => {
+                bitcoin::blockdata::constants::genesis_block(bitcoin::Network::Testnet).header
+            }
+            Network::Regtest => {
+                bitcoin::blockdata::constants::genesis_block(bitcoin::Network::Regtest).header
+            }
+            Network::Signet => {
+                // same coinbase as the other networks, with its own time and
+                // proof of work
+                let mut header =
+                    bitcoin::blockdata::constants::genesis_block(bitcoin::Network::Bitcoin).header;
+                header.time = 1598918400;
+                header.bits = 0x1e0377ae;
+                header.nonce = 52613770;
+                header
+            }
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            Network::Bitcoin => 0,
+            Network::Testnet => 1,
+            Network::Regtest => 2,
+            Network::Signet => 3,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Result<Self> {
+        Ok(match byte {
+            0 => Network::Bitcoin,
+            1 => Network::Testnet,
+            2 => Network::Regtest,
+            3 => Network::Signet,
+            _ => bail!("Unknown network: {}", byte),
+        })
+    }
+}
+
+/// Chains created before the network was configurable ran on testnet.
+impl Default for Network {
+    fn default() -> Self {
+        Network::Testnet
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Network::Bitcoin => "bitcoin",
+            Network::Testnet => "testnet",
+            Network::Regtest => "regtest",
+            Network::Signet => "signet",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Network {
+    type Err = failure::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        Ok(match name {
+            "bitcoin" | "mainnet" => Network::Bitcoin,
+            "testnet" => Network::Testnet,
+            "regtest" => Network::Regtest,
+            "signet" => Network::Signet,
+            _ => bail!("Unknown network: {}", name),
+        })
+    }
+}
+
+impl Encode for Network {
+    fn encode_into<W: Write>(&self, dest: &mut W) -> Result<()> {
+        dest.write_all(&[self.to_byte()])?;
+        Ok(())
+    }
+
+    fn encoding_length(&self) -> Result<usize> {
+        Ok(1)
+    }
+}
+
+impl Decode for Network {
+    fn decode<R: Read>(mut input: R) -> Result<Self> {
+        let mut byte = [0];
+        input.read_exact(&mut byte)?;
+        Network::from_byte(byte[0])
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct EnrichedHeader {
     pub height: u32,
     pub header: BlockHeader,
 }
 
+/// A trusted header the peg's SPV client starts from, given in the genesis
+/// `app_state`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SpvCheckpoint {
+    pub height: u32,
+    pub header: BlockHeader,
+    /// Total work of the chain up to and including the header, as the
+    /// big-endian hex reported by bitcoind's `getblockheader`.
+    pub chainwork: String,
+}
+
+impl SpvCheckpoint {
+    pub fn work(&self) -> Result<Uint256> {
+        let bytes = hex::decode(&self.chainwork)?;
+        if bytes.len() > 32 {
+            bail!("Chain work is longer than 256 bits");
+        }
+        let mut padded = [0; 32];
+        padded[32 - bytes.len()..].copy_from_slice(&bytes);
+
+        // Uint256 words are little-endian
+        let mut words = [0; 4];
+        for (word, chunk) in words.iter_mut().zip(padded.chunks(8).rev()) {
+            let mut word_bytes = [0; 8];
+            word_bytes.copy_from_slice(chunk);
+            *word = u64::from_be_bytes(word_bytes);
+        }
+        Ok(Uint256(words))
+    }
+}
+
 #[derive(Clone)]
 pub struct Script(pub bitcoin::Script);
 
@@ -50,7 +232,7 @@ impl Decode for Script {
     }
 }
 
-#[derive(Clone, Debug, Encode, Decode)]
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode, Serialize, Deserialize)]
 pub struct Outpoint {
     pub txid: [u8; 32],
     pub index: u32,
@@ -73,3 +255,108 @@ impl From<Outpoint> for bitcoin::OutPoint {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::BitcoinHash;
+
+    #[test]
+    fn checkpoint_work() {
+        let checkpoint = |chainwork: &str| SpvCheckpoint {
+            height: 0,
+            header: Network::Regtest.genesis_header(),
+            chainwork: chainwork.to_string(),
+        };
+        assert_eq!(
+            checkpoint("0000000000000000000000000000000000000000000000000000000100010001")
+                .work()
+                .unwrap(),
+            Uint256::from_u64(0x1_0001_0001).unwrap()
+        );
+        assert_eq!(
+            checkpoint("01").work().unwrap(),
+            Uint256::from_u64(1).unwrap()
+        );
+        assert_eq!(
+            checkpoint("0100000000000000000000000000000000000000000000000000000000000002")
+                .work()
+                .unwrap(),
+            Uint256([2, 0, 0, 1 << 56])
+        );
+        assert!(checkpoint("zz").work().is_err());
+        assert!(checkpoint(&"00".repeat(33)).work().is_err());
+    }
+
+    #[test]
+    fn genesis_headers() {
+        let hash = |network: Network| network.genesis_header().bitcoin_hash().to_string();
+        assert_eq!(
+            hash(Network::Bitcoin),
+            "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f"
+        );
+        assert_eq!(
+            hash(Network::Testnet),
+            "000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943"
+        );
+        assert_eq!(
+            hash(Network::Regtest),
+            "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206"
+        );
+        assert_eq!(
+            hash(Network::Signet),
+            "00000008819873e925422c1ff0f99f7cc9bbb232af63a077a480a3633bee1ef6"
+        );
+    }
+
+    #[test]
+    fn network_encoding() {
+        for network in [
+            Network::Bitcoin,
+            Network::Testnet,
+            Network::Regtest,
+            Network::Signet,
+        ]
+        .iter()
+        {
+            let mut bytes = vec![];
+            network.encode_into(&mut bytes).unwrap();
+            assert_eq!(bytes.len(), 1);
+            assert_eq!(Network::decode(bytes.as_slice()).unwrap(), *network);
+            assert_eq!(network.to_string().parse::<Network>().unwrap(), *network);
+        }
+        assert!(Network::decode(&[4][..]).is_err());
+        assert!("litecoin".parse::<Network>().is_err());
+    }
+
+    #[test]
+    fn accepts_addresses() {
+        let accepts = |network: Network, address: &str| {
+            network.accepts_address(&address.parse::<bitcoin::Address>().unwrap())
+        };
+
+        let mainnet_p2pkh = "1CFv3JNJcZLnfaHBcV7bAUPdLezhmMGQ3c";
+        assert!(accepts(Network::Bitcoin, mainnet_p2pkh));
+        assert!(!accepts(Network::Regtest, mainnet_p2pkh));
+
+        // regtest and signet base58 addresses parse as testnet
+        let test_p2pkh = "mrmsLMTHRan3SgkoL45xzPbxCebQcF6efz";
+        let test_p2sh = "2N4W92anmmvAWxXcAQiQ4D3jphXVb66bhfm";
+        for network in [Network::Testnet, Network::Regtest, Network::Signet].iter() {
+            assert!(accepts(*network, test_p2pkh));
+            assert!(accepts(*network, test_p2sh));
+        }
+        assert!(!accepts(Network::Bitcoin, test_p2pkh));
+
+        let mainnet_p2wpkh = "bc1q0dahk7mm0dahk7mm0dahk7mm0dahk7mmy2z623";
+        let testnet_p2wpkh = "tb1q0dahk7mm0dahk7mm0dahk7mm0dahk7mmwvef3z";
+        let regtest_p2wpkh = "bcrt1q0dahk7mm0dahk7mm0dahk7mm0dahk7mmv9qyxt";
+        assert!(accepts(Network::Bitcoin, mainnet_p2wpkh));
+        assert!(accepts(Network::Testnet, testnet_p2wpkh));
+        assert!(accepts(Network::Signet, testnet_p2wpkh));
+        assert!(accepts(Network::Regtest, regtest_p2wpkh));
+        assert!(!accepts(Network::Regtest, testnet_p2wpkh));
+        assert!(!accepts(Network::Testnet, regtest_p2wpkh));
+        assert!(!accepts(Network::Bitcoin, regtest_p2wpkh));
+    }
+}
@@ -1,3 +1,4 @@
+use crate::chain::chain::{params::Params, peg::Params as PegParams};
 use crate::core::bitcoin::bitcoin;
 use crate::Result;
 use bitcoin::consensus::encode::{deserialize, serialize};
@@ -14,6 +15,8 @@ pub enum Transaction {
     Transfer(TransferTransaction),
     Withdrawal(WithdrawalTransaction),
     Signature(SignatureTransaction),
+    Governance(GovernanceTransaction),
+    Pause(PauseTransaction),
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -66,6 +69,8 @@ pub struct DepositTransaction {
 pub struct WithdrawalTransaction {
     pub from: Vec<u8>,
     pub to: bitcoin::Script,
+    /// Amount deducted from the sender. The withdrawal fee is taken out of
+    /// this amount and the rest is paid to `to`.
     pub amount: u64,
     pub signature: Vec<u8>,
     pub nonce: u64,
@@ -74,6 +79,7 @@ pub struct WithdrawalTransaction {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct SignatureTransaction {
     pub signatures: Vec<Vec<u8>>,
+    pub signatory_set_index: u64,
     pub signatory_index: u16,
 }
 
@@ -88,6 +94,46 @@ pub struct TransferTransaction {
     pub fee_amount: u64,
 }
 
+/// A change to the chain which validators can vote on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Proposal {
+    /// Replaces the account and work parameters.
+    SetParams(Params),
+    /// Replaces the peg parameters.
+    SetPegParams(PegParams),
+    /// Starts a new signatory set from the current validators.
+    RotateSignatories,
+    /// Pauses the peg if true, or resumes it if false.
+    SetPegPaused(bool),
+}
+
+/// Submits a governance proposal, or votes for one, on behalf of a validator.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GovernanceTransaction {
+    /// The validator's public key, as it appears in the validator set.
+    pub validator: Vec<u8>,
+    /// The proposal being voted for. A new proposal must use the next unused
+    /// id, so the transaction can't be replayed.
+    pub proposal_id: u64,
+    /// The change to submit, or `None` to vote for an existing proposal.
+    pub proposal: Option<Proposal>,
+    pub signature: Vec<u8>,
+}
+
+/// A validator's signal to pause or resume the peg, which takes effect once
+/// enough voting power has signaled.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PauseTransaction {
+    /// The validator's public key, as it appears in the validator set.
+    pub validator: Vec<u8>,
+    /// True to pause the peg, false to resume it.
+    pub paused: bool,
+    /// The peg's current pause round, so the signal can't be replayed once the
+    /// peg has been paused or resumed.
+    pub round: u64,
+    pub signature: Vec<u8>,
+}
+
 fn verify_signature<S: Sighash>(
     secp: &Secp256k1<VerifyOnly>,
     signature: &[u8],
@@ -115,6 +161,26 @@ impl WithdrawalTransaction {
         verify_signature(secp, self.signature.as_slice(), self.from.as_slice(), self)
     }
 }
+impl GovernanceTransaction {
+    pub fn verify_signature(&self, secp: &Secp256k1<VerifyOnly>) -> Result<bool> {
+        verify_signature(
+            secp,
+            self.signature.as_slice(),
+            self.validator.as_slice(),
+            self,
+        )
+    }
+}
+impl PauseTransaction {
+    pub fn verify_signature(&self, secp: &Secp256k1<VerifyOnly>) -> Result<bool> {
+        verify_signature(
+            secp,
+            self.signature.as_slice(),
+            self.validator.as_slice(),
+            self,
+        )
+    }
+}
 pub trait Sighash {
     fn sighash_input(&self) -> Result<Vec<u8>>;
 
@@ -142,6 +208,22 @@ impl Sighash for WithdrawalTransaction {
     }
 }
 
+impl Sighash for GovernanceTransaction {
+    fn sighash_input(&self) -> Result<Vec<u8>> {
+        let mut sighash_tx = self.clone();
+        sighash_tx.signature = vec![];
+        Ok(bincode::serialize(&sighash_tx)?)
+    }
+}
+
+impl Sighash for PauseTransaction {
+    fn sighash_input(&self) -> Result<Vec<u8>> {
+        let mut sighash_tx = self.clone();
+        sighash_tx.signature = vec![];
+        Ok(bincode::serialize(&sighash_tx)?)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
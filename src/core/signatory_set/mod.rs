@@ -1,9 +1,11 @@
 mod error;
 mod reserve_script;
+mod script_parser;
 mod signatory_set;
 #[cfg(test)]
 mod test_utils;
 
 pub use self::error::*;
 pub use self::reserve_script::*;
+pub use self::script_parser::*;
 pub use self::signatory_set::*;
@@ -1,8 +1,52 @@
-use super::SignatorySet;
+use super::{Recovery, SignatorySet};
 use crate::core::bitcoin::bitcoin::Script;
 use bitcoin_script::bitcoin_script as script;
 
+/// Maximum number of signatories in a signatory set. Each signatory adds 5
+/// non-push opcodes to the redeem script, so this keeps a script with the
+/// largest recovery path within the consensus limit of 201 opcodes (which is
+/// reached well before the 3600-byte P2WSH standardness limit).
+pub const MAX_SIGNATORIES: usize = 34;
+
+/// Maximum number of keys in a recovery path, the OP_CHECKMULTISIG limit.
+pub const MAX_RECOVERY_KEYS: usize = 20;
+
 pub fn redeem_script(signatories: &SignatorySet, data: Vec<u8>) -> Script {
+    recoverable_redeem_script(signatories, data, None)
+}
+
+/// Builds a reserve redeem script which can be spent by more than two thirds
+/// of the signatories' voting power, or, if `recovery` is given, by the
+/// recovery keys once the output has been unspent for the recovery timelock.
+///
+/// With a recovery path the two branches are selected with OP_IF, so the
+/// witness must end with `[1]` for the signatory branch or an empty item for
+/// the recovery branch (see `recovery_witness`).
+pub fn recoverable_redeem_script(
+    signatories: &SignatorySet,
+    data: Vec<u8>,
+    recovery: Option<&Recovery>,
+) -> Script {
+    let mut bytes = vec![];
+
+    match recovery {
+        None => bytes.extend(quorum_script(signatories)),
+        Some(recovery) => {
+            bytes.extend(script!(OP_IF).into_bytes());
+            bytes.extend(quorum_script(signatories));
+            bytes.extend(recovery_script(recovery));
+            bytes.extend(script!(OP_ENDIF).into_bytes());
+        }
+    }
+
+    // depositor data commitment
+    let script = script!(<data> OP_DROP);
+    bytes.extend(&script.into_bytes());
+
+    bytes.into()
+}
+
+fn quorum_script(signatories: &SignatorySet) -> Vec<u8> {
     let truncation = get_truncation(signatories, 23);
 
     let mut iter = signatories.iter();
@@ -42,11 +86,46 @@ pub fn redeem_script(signatories: &SignatorySet, data: Vec<u8>) -> Script {
     };
     bytes.extend(&script.into_bytes());
 
-    // depositor data commitment
-    let script = script!(<data> OP_DROP);
+    bytes
+}
+
+fn recovery_script(recovery: &Recovery) -> Vec<u8> {
+    if recovery.pubkeys.len() > MAX_RECOVERY_KEYS {
+        panic!("Too many recovery keys");
+    }
+    if recovery.threshold == 0 || recovery.threshold as usize > recovery.pubkeys.len() {
+        panic!("Invalid recovery threshold");
+    }
+
+    let script = script! {
+        OP_ELSE
+            <recovery.timelock as i64> OP_CSV OP_DROP
+            <recovery.threshold as i64>
+    };
+    let mut bytes = script.into_bytes();
+
+    for pubkey in recovery.pubkeys.iter().cloned() {
+        bytes.extend(&script!(<pubkey>).into_bytes());
+    }
+
+    let key_count = recovery.pubkeys.len();
+    let script = script!(<key_count as i64> OP_CHECKMULTISIG);
     bytes.extend(&script.into_bytes());
 
-    bytes.into()
+    bytes
+}
+
+/// Builds the witness spending a reserve output through its recovery path.
+/// `signatures` are DER-encoded with their sighash type byte, in the same
+/// order as the recovery keys which made them.
+pub fn recovery_witness(signatures: Vec<Vec<u8>>, redeem_script: &Script) -> Vec<Vec<u8>> {
+    // OP_CHECKMULTISIG pops an extra unused item
+    let mut witness = vec![vec![]];
+    witness.extend(signatures);
+    // selects the recovery branch
+    witness.push(vec![]);
+    witness.push(redeem_script.to_bytes());
+    witness
 }
 
 pub fn output_script(signatories: &SignatorySet, data: Vec<u8>) -> Script {
@@ -61,7 +140,7 @@ fn get_truncation(signatories: &SignatorySet, target_precision: u32) -> u32 {
 
 #[cfg(test)]
 mod tests {
-    use super::super::{test_utils::*, SignatorySet};
+    use super::super::{test_utils::*, Recovery, SignatorySet};
     use super::*;
     use bitcoin_script::bitcoin_script;
 
@@ -150,6 +229,162 @@ mod tests {
         );
     }
 
+    fn mock_recovery() -> Recovery {
+        Recovery {
+            timelock: 4032,
+            threshold: 2,
+            pubkeys: vec![mock_pubkey(7), mock_pubkey(8), mock_pubkey(9)],
+        }
+    }
+
+    #[test]
+    fn recoverable_redeem_script_without_recovery() {
+        let signatories = mock_signatory_set(4);
+        assert_eq!(
+            recoverable_redeem_script(&signatories, vec![1, 2, 3], None),
+            redeem_script(&signatories, vec![1, 2, 3])
+        );
+    }
+
+    #[test]
+    fn recoverable_redeem_script_fixture() {
+        let mut signatories = SignatorySet::new();
+        signatories.set(mock_signatory(1, 5_000_000));
+        signatories.set(mock_signatory(2, 15_000_000));
+        let recovery = mock_recovery();
+        let script = recoverable_redeem_script(&signatories, vec![1, 2, 3], Some(&recovery));
+        let (recovery_key_1, recovery_key_2, recovery_key_3) =
+            (mock_pubkey(7), mock_pubkey(8), mock_pubkey(9));
+
+        assert_eq!(
+            script,
+            bitcoin_script! {
+                OP_IF
+                    0x02531fe6068134503d2723133227c867ac8fa6c83c537e9a44c3c5bdbdcb1fe337 OP_CHECKSIG
+                    OP_IF
+                        3750000
+                    OP_ELSE
+                        0
+                    OP_ENDIF
+
+                    OP_SWAP
+                    0x03462779ad4aad39514614751a71085f2f10e1c7a593e4e030efb5b8721ce55b0b OP_CHECKSIG
+                    OP_IF
+                        1250000 OP_ADD
+                    OP_ENDIF
+
+                    3333333 OP_GREATERTHAN
+                OP_ELSE
+                    4032 OP_CSV OP_DROP
+                    2
+                    <recovery_key_1>
+                    <recovery_key_2>
+                    <recovery_key_3>
+                    3 OP_CHECKMULTISIG
+                OP_ENDIF
+
+                0x010203 OP_DROP
+            }
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid recovery threshold")]
+    fn recoverable_redeem_script_invalid_threshold() {
+        let mut recovery = mock_recovery();
+        recovery.threshold = 4;
+        recoverable_redeem_script(&mock_signatory_set(4), vec![1, 2, 3], Some(&recovery));
+    }
+
+    #[test]
+    fn recovery_witness_fixture() {
+        let recovery = mock_recovery();
+        let script =
+            recoverable_redeem_script(&mock_signatory_set(4), vec![1, 2, 3], Some(&recovery));
+        let witness = recovery_witness(vec![vec![0xaa; 72], vec![0xbb; 71]], &script);
+
+        assert_eq!(
+            witness,
+            vec![
+                vec![],
+                vec![0xaa; 72],
+                vec![0xbb; 71],
+                vec![],
+                script.to_bytes()
+            ]
+        );
+    }
+
+    /// Counts the opcodes which count towards the 201 opcode limit, skipping
+    /// over pushed data.
+    fn count_ops(script: &[u8]) -> usize {
+        let mut ops = 0;
+        let mut i = 0;
+        while i < script.len() {
+            let opcode = script[i];
+            i += 1;
+            match opcode {
+                0x01..=0x4b => i += opcode as usize,
+                0x4c => i += 1 + script[i] as usize,
+                0x4d => i += 2 + u16::from_le_bytes([script[i], script[i + 1]]) as usize,
+                0x61..=0xff => ops += 1,
+                _ => {}
+            }
+        }
+        ops
+    }
+
+    fn max_signatory_set(count: usize) -> SignatorySet {
+        let mut signatories = SignatorySet::new();
+        for i in 0..count {
+            signatories.set(mock_signatory(i as u8 + 1, 10_000_000 + i as u64));
+        }
+        signatories
+    }
+
+    fn max_recovery() -> Recovery {
+        Recovery {
+            timelock: u16::MAX,
+            threshold: MAX_RECOVERY_KEYS as u8,
+            pubkeys: (0..MAX_RECOVERY_KEYS)
+                .map(|i| mock_pubkey(i as u8 + 100))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn max_signatories_limits() {
+        const MAX_OPS_PER_SCRIPT: usize = 201;
+        const MAX_STANDARD_P2WSH_SCRIPT_SIZE: usize = 3600;
+
+        let recovery = max_recovery();
+        let signatories = max_signatory_set(MAX_SIGNATORIES);
+
+        // executing the recovery branch also counts each multisig key
+        let script = recoverable_redeem_script(&signatories, vec![0; 33], Some(&recovery));
+        assert_eq!(
+            count_ops(script.as_bytes()) + MAX_RECOVERY_KEYS,
+            MAX_OPS_PER_SCRIPT - 4
+        );
+        assert!(script.len() <= MAX_STANDARD_P2WSH_SCRIPT_SIZE);
+
+        let script = redeem_script(&signatories, vec![0; 33]);
+        assert_eq!(count_ops(script.as_bytes()), 5 * MAX_SIGNATORIES + 1);
+
+        // one more signatory goes over the limit
+        let signatories = max_signatory_set(MAX_SIGNATORIES + 1);
+        let script = recoverable_redeem_script(&signatories, vec![0; 33], Some(&recovery));
+        assert!(count_ops(script.as_bytes()) + MAX_RECOVERY_KEYS > MAX_OPS_PER_SCRIPT);
+    }
+
+    #[test]
+    #[should_panic(expected = "Too many recovery keys")]
+    fn too_many_recovery_keys() {
+        let mut recovery = max_recovery();
+        recovery.pubkeys.push(mock_pubkey(200));
+        recoverable_redeem_script(&mock_signatory_set(4), vec![1, 2, 3], Some(&recovery));
+    }
+
     #[test]
     fn truncation() {
         // total less than target precision (10, 4 bits)
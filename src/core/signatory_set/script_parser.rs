@@ -0,0 +1,386 @@
+use super::{Recovery, Result, Signatory, SignatorySet};
+use crate::core::bitcoin::bitcoin::PublicKey;
+use failure::bail;
+
+const OP_0: u8 = 0x00;
+const OP_PUSHDATA1: u8 = 0x4c;
+const OP_PUSHDATA2: u8 = 0x4d;
+const OP_PUSHDATA4: u8 = 0x4e;
+const OP_1NEGATE: u8 = 0x4f;
+const OP_1: u8 = 0x51;
+const OP_16: u8 = 0x60;
+const OP_IF: u8 = 0x63;
+const OP_ELSE: u8 = 0x67;
+const OP_ENDIF: u8 = 0x68;
+const OP_DROP: u8 = 0x75;
+const OP_SWAP: u8 = 0x7c;
+const OP_ADD: u8 = 0x93;
+const OP_GREATERTHAN: u8 = 0xa0;
+const OP_CHECKSIG: u8 = 0xac;
+const OP_CHECKMULTISIG: u8 = 0xae;
+const OP_CSV: u8 = 0xb2;
+
+/// The contents of a reserve redeem script, as built by
+/// `recoverable_redeem_script`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ParsedRedeemScript {
+    /// The signatories, with the truncated voting powers committed to in the
+    /// script rather than their actual voting powers.
+    pub signatories: SignatorySet,
+    /// The truncated voting power which the signatures must exceed.
+    pub threshold: u64,
+    /// The depositor data commitment.
+    pub data: Vec<u8>,
+    pub recovery: Option<Recovery>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Instruction {
+    Push(Vec<u8>),
+    Op(u8),
+}
+
+/// Decodes a reserve redeem script (the last witness item of an input
+/// spending a reserve output) back into the signatories, threshold, data
+/// commitment and recovery path it was built from. Fails for any script which
+/// does not have exactly the layout of a reserve script.
+pub fn parse_redeem_script(script: &[u8]) -> Result<ParsedRedeemScript> {
+    let mut parser = Parser {
+        instructions: tokenize(script)?,
+        position: 0,
+    };
+
+    let recovery_path = parser.peek() == Some(&Instruction::Op(OP_IF));
+    if recovery_path {
+        parser.expect_op(OP_IF)?;
+    }
+
+    let (signatories, threshold) = parser.quorum()?;
+
+    let recovery = if recovery_path {
+        let recovery = parser.recovery()?;
+        parser.expect_op(OP_ENDIF)?;
+        Some(recovery)
+    } else {
+        None
+    };
+
+    let data = parser.push()?;
+    parser.expect_op(OP_DROP)?;
+
+    if parser.peek().is_some() {
+        bail!("Unexpected instructions after reserve script");
+    }
+
+    Ok(ParsedRedeemScript {
+        signatories,
+        threshold,
+        data,
+        recovery,
+    })
+}
+
+fn tokenize(script: &[u8]) -> Result<Vec<Instruction>> {
+    fn take<'a>(script: &'a [u8], i: &mut usize, len: usize) -> Result<&'a [u8]> {
+        if script.len() - *i < len {
+            bail!("Script ends in the middle of a push");
+        }
+        let bytes = &script[*i..*i + len];
+        *i += len;
+        Ok(bytes)
+    }
+
+    let mut instructions = vec![];
+    let mut i = 0;
+    while i < script.len() {
+        let opcode = script[i];
+        i += 1;
+        let len = match opcode {
+            OP_0..=0x4b => opcode as usize,
+            OP_PUSHDATA1 => take(script, &mut i, 1)?[0] as usize,
+            OP_PUSHDATA2 => {
+                let bytes = take(script, &mut i, 2)?;
+                u16::from_le_bytes([bytes[0], bytes[1]]) as usize
+            }
+            OP_PUSHDATA4 => {
+                let bytes = take(script, &mut i, 4)?;
+                u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize
+            }
+            _ => {
+                instructions.push(Instruction::Op(opcode));
+                continue;
+            }
+        };
+        instructions.push(Instruction::Push(take(script, &mut i, len)?.to_vec()));
+    }
+    Ok(instructions)
+}
+
+/// Decodes a minimally-encoded script number of up to 5 bytes, the longest
+/// accepted by any opcode (OP_CHECKSEQUENCEVERIFY).
+fn decode_script_num(bytes: &[u8]) -> Result<i64> {
+    if bytes.len() > 5 {
+        bail!("Script number is too long");
+    }
+    let last = match bytes.last() {
+        None => return Ok(0),
+        Some(last) => *last,
+    };
+    if last & 0x7f == 0 && (bytes.len() == 1 || bytes[bytes.len() - 2] & 0x80 == 0) {
+        bail!("Script number is not minimally encoded");
+    }
+
+    let mut value = 0i64;
+    for (i, byte) in bytes.iter().enumerate() {
+        value |= (*byte as i64) << (8 * i);
+    }
+    if last & 0x80 != 0 {
+        value &= !(0x80i64 << (8 * (bytes.len() - 1)));
+        value = -value;
+    }
+    Ok(value)
+}
+
+struct Parser {
+    instructions: Vec<Instruction>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Instruction> {
+        self.instructions.get(self.position)
+    }
+
+    fn next_instruction(&mut self) -> Result<&Instruction> {
+        match self.instructions.get(self.position) {
+            None => bail!("Reserve script ended unexpectedly"),
+            Some(instruction) => {
+                self.position += 1;
+                Ok(instruction)
+            }
+        }
+    }
+
+    fn expect_op(&mut self, expected: u8) -> Result<()> {
+        let position = self.position;
+        match self.next_instruction()? {
+            Instruction::Op(opcode) if *opcode == expected => Ok(()),
+            _ => bail!(
+                "Expected opcode 0x{:02x} at instruction {}",
+                expected,
+                position
+            ),
+        }
+    }
+
+    fn push(&mut self) -> Result<Vec<u8>> {
+        let position = self.position;
+        match self.next_instruction()? {
+            Instruction::Push(bytes) => Ok(bytes.clone()),
+            _ => bail!("Expected data push at instruction {}", position),
+        }
+    }
+
+    fn number(&mut self) -> Result<i64> {
+        let position = self.position;
+        match self.next_instruction()? {
+            Instruction::Push(bytes) => decode_script_num(bytes),
+            Instruction::Op(OP_1NEGATE) => Ok(-1),
+            Instruction::Op(opcode) if (OP_1..=OP_16).contains(opcode) => {
+                Ok((opcode - OP_1 + 1) as i64)
+            }
+            _ => bail!("Expected number at instruction {}", position),
+        }
+    }
+
+    fn unsigned<T: std::convert::TryFrom<i64>>(&mut self) -> Result<T> {
+        let number = self.number()?;
+        match T::try_from(number) {
+            Ok(value) => Ok(value),
+            Err(_) => bail!("Number {} is out of range", number),
+        }
+    }
+
+    fn pubkey(&mut self) -> Result<PublicKey> {
+        let bytes = self.push()?;
+        Ok(PublicKey::from_slice(bytes.as_slice())?)
+    }
+
+    fn quorum(&mut self) -> Result<(SignatorySet, u64)> {
+        fn add(signatories: &mut SignatorySet, pubkey: PublicKey, voting_power: u64) -> Result<()> {
+            if signatories
+                .set(Signatory::new(pubkey, voting_power))
+                .is_some()
+            {
+                bail!("Reserve script contains a signatory more than once");
+            }
+            Ok(())
+        }
+
+        let mut signatories = SignatorySet::new();
+
+        // first signatory
+        let pubkey = self.pubkey()?;
+        self.expect_op(OP_CHECKSIG)?;
+        self.expect_op(OP_IF)?;
+        let voting_power = self.unsigned()?;
+        self.expect_op(OP_ELSE)?;
+        if self.number()? != 0 {
+            bail!("Expected 0 for a missing first signature");
+        }
+        self.expect_op(OP_ENDIF)?;
+        add(&mut signatories, pubkey, voting_power)?;
+
+        // all other signatories
+        while self.peek() == Some(&Instruction::Op(OP_SWAP)) {
+            self.expect_op(OP_SWAP)?;
+            let pubkey = self.pubkey()?;
+            self.expect_op(OP_CHECKSIG)?;
+            self.expect_op(OP_IF)?;
+            let voting_power = self.unsigned()?;
+            self.expect_op(OP_ADD)?;
+            self.expect_op(OP_ENDIF)?;
+            add(&mut signatories, pubkey, voting_power)?;
+        }
+
+        // > 2/3 check
+        let threshold = self.unsigned()?;
+        self.expect_op(OP_GREATERTHAN)?;
+
+        Ok((signatories, threshold))
+    }
+
+    fn recovery(&mut self) -> Result<Recovery> {
+        self.expect_op(OP_ELSE)?;
+        let timelock = self.unsigned()?;
+        self.expect_op(OP_CSV)?;
+        self.expect_op(OP_DROP)?;
+        let threshold = self.unsigned()?;
+
+        let mut pubkeys = vec![];
+        while let Some(Instruction::Push(bytes)) = self.peek() {
+            if bytes.len() < 33 {
+                break;
+            }
+            pubkeys.push(self.pubkey()?);
+        }
+
+        let key_count: usize = self.unsigned()?;
+        if key_count != pubkeys.len() {
+            bail!(
+                "Recovery path has {} keys but checks {}",
+                pubkeys.len(),
+                key_count
+            );
+        }
+        self.expect_op(OP_CHECKMULTISIG)?;
+
+        Ok(Recovery {
+            timelock,
+            threshold,
+            pubkeys,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{recoverable_redeem_script, redeem_script, test_utils::*};
+    use super::*;
+
+    #[test]
+    fn parse_fixture() {
+        let mut signatories = SignatorySet::new();
+        signatories.set(mock_signatory(1, 5_000_000));
+        signatories.set(mock_signatory(2, 15_000_000));
+        signatories.set(mock_signatory(3, 20_000_000));
+        signatories.set(mock_signatory(4, 60_000_000));
+        let script = redeem_script(&signatories, vec![1, 2, 3]);
+
+        let mut truncated = SignatorySet::new();
+        truncated.set(mock_signatory(1, 312_500));
+        truncated.set(mock_signatory(2, 937_500));
+        truncated.set(mock_signatory(3, 1_250_000));
+        truncated.set(mock_signatory(4, 3_750_000));
+
+        assert_eq!(
+            parse_redeem_script(script.as_bytes()).unwrap(),
+            ParsedRedeemScript {
+                signatories: truncated,
+                threshold: 4_166_666,
+                data: vec![1, 2, 3],
+                recovery: None,
+            }
+        );
+    }
+
+    #[test]
+    fn parse_small_voting_powers() {
+        // voting powers and data which are encoded as OP_0 and OP_1..OP_16
+        let signatories = mock_signatory_set(4);
+        let script = redeem_script(&signatories, vec![]);
+        let parsed = parse_redeem_script(script.as_bytes()).unwrap();
+
+        assert_eq!(parsed.signatories, signatories);
+        assert_eq!(parsed.threshold, 6);
+        assert_eq!(parsed.data, Vec::<u8>::new());
+    }
+
+    #[test]
+    fn parse_recovery() {
+        let signatories = mock_signatory_set(3);
+        let recovery = Recovery {
+            timelock: 4032,
+            threshold: 2,
+            pubkeys: vec![mock_pubkey(7), mock_pubkey(8), mock_pubkey(9)],
+        };
+        let data = mock_pubkey(10).to_bytes();
+        let script = recoverable_redeem_script(&signatories, data.clone(), Some(&recovery));
+        let parsed = parse_redeem_script(script.as_bytes()).unwrap();
+
+        assert_eq!(parsed.signatories, signatories);
+        assert_eq!(parsed.data, data);
+        assert_eq!(parsed.recovery, Some(recovery));
+    }
+
+    #[test]
+    fn parse_long_data() {
+        let signatories = mock_signatory_set(2);
+        let script = redeem_script(&signatories, vec![123; 300]);
+        let parsed = parse_redeem_script(script.as_bytes()).unwrap();
+        assert_eq!(parsed.data, vec![123; 300]);
+    }
+
+    #[test]
+    fn parse_invalid() {
+        let script = redeem_script(&mock_signatory_set(4), vec![1, 2, 3]).into_bytes();
+
+        // truncated
+        assert!(parse_redeem_script(&script[..script.len() - 1]).is_err());
+        assert!(parse_redeem_script(&script[..20]).is_err());
+        // trailing opcode
+        let mut extended = script.clone();
+        extended.push(OP_DROP);
+        assert!(parse_redeem_script(&extended).is_err());
+        // OP_GREATERTHAN replaced with OP_LESSTHAN
+        let mut modified = script.clone();
+        let index = modified.len() - 6;
+        assert_eq!(modified[index], OP_GREATERTHAN);
+        modified[index] = 0x9f;
+        assert!(parse_redeem_script(&modified).is_err());
+        assert!(parse_redeem_script(&[]).is_err());
+    }
+
+    #[test]
+    fn script_nums() {
+        assert_eq!(decode_script_num(&[]).unwrap(), 0);
+        assert_eq!(decode_script_num(&[0x7f]).unwrap(), 127);
+        assert_eq!(decode_script_num(&[0x80, 0x00]).unwrap(), 128);
+        assert_eq!(decode_script_num(&[0x81]).unwrap(), -1);
+        assert_eq!(decode_script_num(&[0x70, 0x38, 0x39]).unwrap(), 3_750_000);
+        assert_eq!(decode_script_num(&[0xff, 0xff, 0x00]).unwrap(), 65535);
+        assert!(decode_script_num(&[0x01, 0x00]).is_err());
+        assert!(decode_script_num(&[0x00]).is_err());
+        assert!(decode_script_num(&[1; 6]).is_err());
+    }
+}
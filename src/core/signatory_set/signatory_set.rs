@@ -1,6 +1,8 @@
+use super::MAX_RECOVERY_KEYS;
 use crate::core::bitcoin::bitcoin;
 use crate::core::primitives::Result;
-use bitcoin::PublicKey;
+use bitcoin::{PublicKey, Script};
+use failure::bail;
 use orga::{Decode, Encode, Terminated};
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeSet, HashMap};
@@ -69,6 +71,16 @@ impl SignatorySet {
     pub fn iter(&self) -> impl Iterator<Item = &Signatory> {
         self.set.iter().rev()
     }
+
+    /// Removes the lowest-ranked signatories until at most `max` remain.
+    /// Signatories are ranked by voting power with ties broken by public key,
+    /// the same order as `iter`, so the result is deterministic.
+    pub fn truncate(&mut self, max: usize) {
+        while self.len() > max {
+            let lowest = self.set.iter().next().unwrap().pubkey.clone();
+            self.remove(&lowest);
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -77,10 +89,90 @@ struct SerializableSignatorySetSnapshot {
     signatories: Vec<(Vec<u8>, u64)>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+struct SerializableRecovery {
+    timelock: u16,
+    threshold: u8,
+    pubkeys: Vec<Vec<u8>>,
+}
+
+/// An emergency spending path for reserve outputs. Once an output has gone
+/// unspent for `timelock` blocks, `threshold` of the recovery `pubkeys` can
+/// spend it without the signatories.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Recovery {
+    /// Relative timelock in blocks, enforced with OP_CHECKSEQUENCEVERIFY.
+    pub timelock: u16,
+    pub threshold: u8,
+    pub pubkeys: Vec<PublicKey>,
+}
+
+impl Recovery {
+    /// Returns how often, in seconds, the reserve should be moved to fresh
+    /// outputs so the recovery path never becomes spendable while the
+    /// signatories are live. This is half the timelock at 10 minutes a block.
+    pub fn refresh_interval(&self) -> u64 {
+        self.timelock as u64 * 600 / 2
+    }
+
+    /// Checks that a recovery script can be built for this path, which must be
+    /// done before it is used for reserve outputs.
+    pub fn validate(&self) -> Result<()> {
+        if self.timelock == 0 {
+            bail!("Recovery timelock must be greater than 0");
+        }
+        if self.pubkeys.is_empty() || self.pubkeys.len() > MAX_RECOVERY_KEYS {
+            bail!("Recovery path must have 1 to {} keys", MAX_RECOVERY_KEYS);
+        }
+        if self.threshold == 0 || self.threshold as usize > self.pubkeys.len() {
+            bail!("Invalid recovery threshold");
+        }
+        Ok(())
+    }
+
+    fn to_serializable(&self) -> SerializableRecovery {
+        SerializableRecovery {
+            timelock: self.timelock,
+            threshold: self.threshold,
+            pubkeys: self
+                .pubkeys
+                .iter()
+                .map(|pubkey| pubkey.to_bytes())
+                .collect(),
+        }
+    }
+
+    fn from_serializable(serializable: &SerializableRecovery) -> Result<Self> {
+        let pubkeys = serializable
+            .pubkeys
+            .iter()
+            .map(|bytes| PublicKey::from_slice(bytes))
+            .collect::<std::result::Result<_, _>>()?;
+        Ok(Recovery {
+            timelock: serializable.timelock,
+            threshold: serializable.threshold,
+            pubkeys,
+        })
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let serializable =
+            bincode::deserialize(bytes).map_err(|err| failure::format_err!("{}", err))?;
+        Ok(Self::from_serializable(&serializable)?)
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let serializable = self.to_serializable();
+        bincode::serialize(&serializable).map_err(|err| failure::format_err!("{}", err))
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct SignatorySetSnapshot {
     pub time: u64,
     pub signatories: SignatorySet,
+    /// The recovery path included in this set's reserve scripts, if any.
+    pub recovery: Option<Recovery>,
 }
 
 impl SignatorySetSnapshot {
@@ -107,18 +199,44 @@ impl SignatorySetSnapshot {
         Ok(SignatorySetSnapshot {
             time: serializable.time,
             signatories,
+            recovery: None,
         })
     }
 
+    /// Decodes a snapshot. The recovery path is encoded after the signatories,
+    /// only if present, so snapshots encoded before recovery paths existed
+    /// still decode.
     pub fn decode(bytes: &[u8]) -> Result<Self> {
-        let serializable =
-            bincode::deserialize(bytes).map_err(|err| failure::format_err!("{}", err))?;
-        Ok(Self::from_serializable(&serializable)?)
+        let mut reader = bytes;
+        let serializable = bincode::deserialize_from(&mut reader)
+            .map_err(|err| failure::format_err!("{}", err))?;
+        let mut snapshot = Self::from_serializable(&serializable)?;
+        if !reader.is_empty() {
+            snapshot.recovery = Some(Recovery::decode(reader)?);
+        }
+        Ok(snapshot)
     }
 
     pub fn encode(&self) -> Result<Vec<u8>> {
         let serializable = self.to_serializable();
-        bincode::serialize(&serializable).map_err(|err| failure::format_err!("{}", err))
+        let mut bytes =
+            bincode::serialize(&serializable).map_err(|err| failure::format_err!("{}", err))?;
+        if let Some(recovery) = &self.recovery {
+            bytes.extend(recovery.encode()?);
+        }
+        Ok(bytes)
+    }
+
+    /// Returns the redeem script for a reserve output paying to this set,
+    /// including the set's recovery path if it has one.
+    pub fn redeem_script(&self, data: Vec<u8>) -> Script {
+        super::recoverable_redeem_script(&self.signatories, data, self.recovery.as_ref())
+    }
+
+    /// Returns the P2WSH output script for a reserve output paying to this
+    /// set.
+    pub fn output_script(&self, data: Vec<u8>) -> Script {
+        self.redeem_script(data).to_v0_p2wsh()
     }
 }
 
@@ -146,6 +264,29 @@ impl Decode for SignatorySetSnapshot {
 
 impl !Terminated for SignatorySetSnapshot {}
 
+impl Encode for Recovery {
+    fn encode_into<W: Write>(&self, dest: &mut W) -> Result<()> {
+        let bytes = Recovery::encode(self)?;
+        dest.write_all(bytes.as_slice())?;
+        Ok(())
+    }
+
+    fn encoding_length(&self) -> Result<usize> {
+        let bytes = Recovery::encode(self)?;
+        Ok(bytes.len())
+    }
+}
+
+impl Decode for Recovery {
+    fn decode<R: Read>(mut input: R) -> Result<Self> {
+        let mut buf = vec![];
+        input.read_to_end(&mut buf)?;
+        Recovery::decode(buf.as_slice())
+    }
+}
+
+impl !Terminated for Recovery {}
+
 #[cfg(test)]
 mod tests {
     use super::super::test_utils::*;
@@ -201,6 +342,36 @@ mod tests {
         assert_eq!(iter.next().unwrap(), &mock_signatory(3, 100));
     }
 
+    #[test]
+    fn truncate() {
+        let mut set = SignatorySet::new();
+        set.add(mock_signatory(1, 100));
+        set.add(mock_signatory(2, 300));
+        set.add(mock_signatory(3, 100));
+        set.add(mock_signatory(4, 200));
+
+        let mut truncated = set.clone();
+        truncated.truncate(2);
+        assert_eq!(truncated.len(), 2);
+        assert_eq!(truncated.total_voting_power(), 500);
+        let mut iter = truncated.iter();
+        assert_eq!(iter.next().unwrap(), &mock_signatory(2, 300));
+        assert_eq!(iter.next().unwrap(), &mock_signatory(4, 200));
+
+        // ties are broken the same way as in iteration order
+        let mut truncated = set.clone();
+        truncated.truncate(3);
+        assert_eq!(truncated.len(), 3);
+        assert_eq!(
+            truncated.iter().last().unwrap(),
+            set.iter().skip(2).next().unwrap()
+        );
+
+        let mut truncated = set.clone();
+        truncated.truncate(10);
+        assert_eq!(truncated, set);
+    }
+
     #[test]
     fn snapshot_encode_fixture() {
         let mut set = SignatorySet::new();
@@ -210,6 +381,7 @@ mod tests {
         let snapshot = SignatorySetSnapshot {
             time: 123,
             signatories: set,
+            recovery: None,
         };
         assert_eq!(
             snapshot.encode().unwrap(),
@@ -233,6 +405,7 @@ mod tests {
         let expected_snapshot = SignatorySetSnapshot {
             time: 123,
             signatories: set,
+            recovery: None,
         };
 
         let bytes = vec![
@@ -248,4 +421,25 @@ mod tests {
             expected_snapshot
         );
     }
+
+    #[test]
+    fn snapshot_recovery_roundtrip() {
+        let mut set = SignatorySet::new();
+        set.add(mock_signatory(1, 200));
+
+        let snapshot = SignatorySetSnapshot {
+            time: 123,
+            signatories: set,
+            recovery: Some(Recovery {
+                timelock: 4032,
+                threshold: 2,
+                pubkeys: vec![mock_pubkey(7), mock_pubkey(8), mock_pubkey(9)],
+            }),
+        };
+        let bytes = snapshot.encode().unwrap();
+        assert_eq!(
+            SignatorySetSnapshot::decode(bytes.as_slice()).unwrap(),
+            snapshot
+        );
+    }
 }
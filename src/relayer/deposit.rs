@@ -1,9 +1,8 @@
 use crate::chain::client::Client as PegClient;
-use crate::core::bitcoin::{bitcoin, bitcoincore_rpc};
+use crate::core::bitcoin::{bitcoin, bitcoincore_rpc, Network};
 use crate::core::primitives::transaction::{DepositTransaction, Transaction};
-use crate::core::signatory_set::SignatorySet;
+use crate::core::signatory_set::SignatorySetSnapshot;
 use crate::Result;
-use bitcoin::Network::Testnet as bitcoin_network;
 use bitcoincore_rpc::{Client, RpcApi};
 use log::{debug, warn};
 use std::collections::HashSet;
@@ -63,19 +62,17 @@ fn build_deposit_tx(
 }
 
 fn possible_bitcoin_addresses(
-    signatory_sets: Vec<SignatorySet>,
+    signatory_sets: Vec<SignatorySetSnapshot>,
     possible_recipients: Vec<Vec<u8>>,
+    network: Network,
 ) -> Vec<(bitcoin::Address, Vec<u8>)> {
     let result = signatory_sets
         .iter()
         .map(|signatory_set| {
             possible_recipients.iter().map(move |possible_recipient| {
-                let script = crate::core::signatory_set::redeem_script(
-                    &signatory_set,
-                    possible_recipient.clone(),
-                );
+                let script = signatory_set.redeem_script(possible_recipient.clone());
                 (
-                    bitcoin::Address::p2wsh(&script, bitcoin_network),
+                    bitcoin::Address::p2wsh(&script, network.address_network()),
                     possible_recipient.clone(),
                 )
             })
@@ -91,9 +88,10 @@ pub fn relay_deposits(
     btc_rpc: &Client,
     peg_client: &PegClient,
 ) -> Result<()> {
-    let signatory_sets = peg_client.get_signatory_sets()?;
+    let signatory_sets = peg_client.get_signatory_set_snapshots()?;
+    let network = peg_client.get_network()?;
     let recipients = possible_recipients.iter().cloned().collect();
-    let possible_addresses = possible_bitcoin_addresses(signatory_sets, recipients)
+    let possible_addresses = possible_bitcoin_addresses(signatory_sets, recipients, network)
         .into_iter()
         .rev();
     for (address, recipient) in possible_addresses {
@@ -120,11 +118,12 @@ pub fn import_addresses(
     peg_client: &PegClient,
 ) -> Result<()> {
     debug!("Getting signatory sets");
-    let signatory_sets = peg_client.get_signatory_sets()?;
+    let signatory_sets = peg_client.get_signatory_set_snapshots()?;
     debug!("Got signatory sets");
+    let network = peg_client.get_network()?;
     let recipients = possible_recipients.into_iter().collect();
     debug!("Deriving possible bitcoin addresses");
-    for (address, _) in possible_bitcoin_addresses(signatory_sets, recipients) {
+    for (address, _) in possible_bitcoin_addresses(signatory_sets, recipients, network) {
         btc_rpc.import_address(&address, None, Some(false), None)?;
     }
     debug!("Imported bitcoin addresses");
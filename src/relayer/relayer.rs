@@ -1,7 +1,7 @@
 use super::address_pool::AddressPool;
 use super::deposit::{import_addresses, relay_deposits};
 use crate::chain::client::Client as PegClient;
-use crate::core::bitcoin::{bitcoin, bitcoincore_rpc};
+use crate::core::bitcoin::{bitcoin, bitcoincore_rpc, Network};
 use crate::core::primitives::transaction::{HeaderTransaction, Transaction};
 use crate::Result;
 use bitcoin::hash_types::BlockHash as Hash;
@@ -11,12 +11,13 @@ use log::{debug, info};
 use std::collections::HashSet;
 use std::env;
 
-pub fn make_rpc_client() -> Result<Client> {
+/// Connects to the local Bitcoin Core node's RPC server for the given network.
+pub fn make_rpc_client(network: Network) -> Result<Client> {
     let rpc_user = env::var("BTC_RPC_USER")?;
     let rpc_pass = env::var("BTC_RPC_PASS")?;
     let rpc_auth = Auth::UserPass(rpc_user, rpc_pass);
-    let rpc_url = "http://localhost:18332";
-    Ok(Client::new(rpc_url.to_string(), rpc_auth)?)
+    let rpc_url = format!("http://localhost:{}", network.rpc_port());
+    Ok(Client::new(rpc_url, rpc_auth)?)
 }
 
 /// Iterate over peg hashes, starting from the tip and going backwards.
@@ -97,8 +98,8 @@ pub fn start() {
     let mut addresses = HashSet::new();
 
     let mut deposit_step = || -> Result<()> {
-        let btc_rpc = make_rpc_client().unwrap();
         let peg_client = PegClient::new("localhost:26657")?;
+        let btc_rpc = make_rpc_client(peg_client.get_network()?).unwrap();
 
         let new_addresses = address_pool
             .drain_addresses()
@@ -142,8 +143,8 @@ pub fn start() {
 }
 
 fn header_step() -> Result<()> {
-    let btc_rpc = make_rpc_client().unwrap();
     let peg_client = PegClient::new("localhost:26657")?;
+    let btc_rpc = make_rpc_client(peg_client.get_network()?).unwrap();
 
     // Fetch peg hashes
     let peg_hashes = peg_client.get_bitcoin_block_hashes()?;
@@ -160,8 +161,8 @@ fn header_step() -> Result<()> {
 }
 
 fn checkpoint_step() -> Result<()> {
-    let btc_rpc = make_rpc_client().unwrap();
     let peg_client = PegClient::new("localhost:26657")?;
+    let btc_rpc = make_rpc_client(peg_client.get_network()?).unwrap();
 
     let btc_tx = match peg_client.get_finalized_checkpoint_tx()? {
         None => return Ok(()),
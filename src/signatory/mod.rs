@@ -1,6 +1,10 @@
+use crate::chain::chain::peg::input_signatory_set_indexes;
 use crate::chain::client::{Client, RpcError};
 use crate::core::bitcoin::bitcoin;
-use crate::core::primitives::{transaction::Transaction, Result};
+use crate::core::primitives::{
+    transaction::{PauseTransaction, Sighash, SignatureTransaction, Transaction},
+    Result,
+};
 use lazy_static::lazy_static;
 use log::{info, warn};
 use secp256k1::{Secp256k1, SecretKey, SignOnly};
@@ -13,8 +17,8 @@ lazy_static! {
     static ref SECP: Secp256k1<SignOnly> = Secp256k1::signing_only();
 }
 
-pub fn start<P: AsRef<Path>>(nomic_home: P) -> Result<()> {
-    let client = Client::new("localhost:26657")?;
+/// Reads the private key of the local Tendermint validator.
+pub fn load_validator_key<P: AsRef<Path>>(nomic_home: P) -> Result<SecretKey> {
     let key_path = nomic_home
         .as_ref()
         .join("config")
@@ -25,7 +29,34 @@ pub fn start<P: AsRef<Path>>(nomic_home: P) -> Result<()> {
     let priv_key_str = priv_key_value
         .as_str()
         .expect("Invalid Tendermint private key");
-    let priv_key = SecretKey::from_slice(base64::decode(priv_key_str)?.as_slice())?;
+    Ok(SecretKey::from_slice(
+        base64::decode(priv_key_str)?.as_slice(),
+    )?)
+}
+
+/// Signs and broadcasts the local validator's signal to pause or resume the
+/// peg.
+pub fn signal_pause<P: AsRef<Path>>(nomic_home: P, paused: bool) -> Result<()> {
+    let client = Client::new("localhost:26657")?;
+    let priv_key = load_validator_key(nomic_home)?;
+    let pub_key = secp256k1::PublicKey::from_secret_key(&SECP, &priv_key);
+
+    let mut tx = PauseTransaction {
+        validator: pub_key.serialize().to_vec(),
+        paused,
+        round: client.get_pause_round()?,
+        signature: vec![],
+    };
+    let message = secp256k1::Message::from_slice(tx.sighash()?.as_slice())?;
+    tx.signature = SECP.sign(&message, &priv_key).serialize_compact().to_vec();
+
+    client.send(Transaction::Pause(tx))?;
+    Ok(())
+}
+
+pub fn start<P: AsRef<Path>>(nomic_home: P) -> Result<()> {
+    let client = Client::new("localhost:26657")?;
+    let priv_key = load_validator_key(nomic_home)?;
 
     let pub_key = secp256k1::PublicKey::from_secret_key(&SECP, &priv_key);
     println!("signatory pub key: {:?}", &pub_key.serialize()[..]);
@@ -44,59 +75,80 @@ fn try_sign(client: &Client, priv_key: &SecretKey) -> Result<()> {
         Some(tx) => tx,
     };
 
-    let signatory_set_index = client
-        .state()?
-        .peg
-        .active_checkpoint
-        .signatory_set_index
-        .get()?;
-    let signatories = client
-        .state()?
-        .peg
-        .signatory_sets
-        .get_fixed(signatory_set_index)?
-        .signatories;
+    let utxos = client.state()?.peg.active_utxos()?;
+    let set_indexes = input_signatory_set_indexes(&utxos);
 
     let pub_key = secp256k1::PublicKey::from_secret_key(&SECP, &priv_key);
 
-    let mut signatory_index = None;
-    for (i, signatory) in signatories.iter().enumerate() {
-        if signatory.pubkey.key == pub_key {
-            signatory_index = Some(i);
-            break;
+    // Sign the inputs of each signatory set we are a member of
+    for signatory_set_index in set_indexes.iter().cloned() {
+        let snapshot = client
+            .state()?
+            .peg
+            .signatory_sets
+            .get_fixed(signatory_set_index)?;
+
+        let mut signatory_index = None;
+        for (i, signatory) in snapshot.signatories.iter().enumerate() {
+            if signatory.pubkey.key == pub_key {
+                signatory_index = Some(i);
+                break;
+            }
+        }
+        let signatory_index = match signatory_index {
+            None => continue,
+            Some(index) => index,
+        };
+
+        let slot = client
+            .state()?
+            .peg
+            .signature_offset(&set_indexes, signatory_set_index)?
+            + signatory_index as u64;
+        if client
+            .state()?
+            .peg
+            .active_checkpoint
+            .signatures
+            .get(slot)?
+            .is_some()
+        {
+            continue;
         }
+
+        info!(
+            "Signing active checkpoint tx for signatory set {}: {:?}",
+            signatory_set_index, &btc_tx
+        );
+
+        let signatures =
+            utxos
+                .iter()
+                .enumerate()
+                .filter(|(_, utxo)| utxo.signatory_set_index == signatory_set_index)
+                .map(|(i, utxo)| {
+                    let script = snapshot.redeem_script(utxo.data.clone());
+                    let sighash = bitcoin::util::bip143::SighashComponents::new(&btc_tx)
+                        .sighash_all(&btc_tx.input[i], &script, utxo.value);
+                    let message = secp256k1::Message::from_slice(&sighash[..])?;
+                    let sig = SECP.sign(&message, &priv_key);
+                    Ok(sig.serialize_compact().to_vec())
+                })
+                .collect::<Result<_>>()?;
+
+        let tx = SignatureTransaction {
+            signatures,
+            signatory_set_index,
+            signatory_index: signatory_index as u16,
+        };
+
+        send_signatures(client, tx)?;
     }
-    let signatory_index = match signatory_index {
-        None => return Ok(()),
-        Some(index) => index,
-    };
 
-    info!("Signing active checkpoint tx: {:?}", &btc_tx);
-
-    let signatures = client
-        .state()?
-        .peg
-        .active_utxos()?
-        .iter()
-        .enumerate()
-        .map(|(i, utxo)| {
-            let script = crate::core::signatory_set::redeem_script(&signatories, utxo.data.clone());
-            let sighash = bitcoin::util::bip143::SighashComponents::new(&btc_tx).sighash_all(
-                &btc_tx.input[i],
-                &script,
-                utxo.value,
-            );
-            let message = secp256k1::Message::from_slice(&sighash[..])?;
-            let sig = SECP.sign(&message, &priv_key);
-            Ok(sig.serialize_compact().to_vec())
-        })
-        .collect::<Result<_>>()?;
-
-    let tx = crate::core::primitives::transaction::SignatureTransaction {
-        signatures,
-        signatory_index: signatory_index as u16,
-    };
+    Ok(())
+}
 
+fn send_signatures(client: &Client, tx: SignatureTransaction) -> Result<()> {
     if let Err(err) = client.send(Transaction::Signature(tx)) {
         let err: RpcError = err.downcast()?;
         dbg!(&err);
@@ -5,8 +5,6 @@ use log::info;
 use rand::random;
 use sha2::{Digest, Sha256};
 
-const MIN_WORK: u64 = 1 << 20;
-
 pub fn generate() {
     let rpc = PegClient::new("localhost:26657").unwrap();
     let pub_key_bytes = block_on(rpc.tendermint_rpc.status())
@@ -14,11 +12,12 @@ pub fn generate() {
         .validator_info
         .pub_key
         .as_bytes();
+    let min_work = rpc.get_params().unwrap().min_work;
 
     let mut nonce = random::<u64>();
     loop {
         let work_value = try_nonce(&pub_key_bytes, nonce);
-        if work_value >= MIN_WORK {
+        if work_value >= min_work {
             info!("Generated {} voting power", work_value);
             rpc.submit_work_proof(&pub_key_bytes.to_vec(), nonce)
                 .expect("Failed to submit work proof");